@@ -0,0 +1,383 @@
+use super::ImageItem;
+use burn::data::dataset::Dataset;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    f32::consts::PI,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Random changes applied to training images, each stage is off unless configured
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct AugmentationConfig {
+    affine: Option<AffineConfig>,
+    elastic: Option<ElasticConfig>,
+    erasing: Option<ErasingConfig>,
+    noise: Option<NoiseConfig>,
+}
+
+/// Rotates, scales and shifts the image around its center
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AffineConfig {
+    #[serde(deserialize_with = "probability")]
+    probability: f64,
+    /// Largest rotation either way, in degrees
+    #[serde(default)]
+    rotation: f32,
+    /// Largest shift either way, as a fraction of the image size
+    #[serde(default)]
+    shift: f32,
+    /// Largest change in size either way, e.g. 0.1 for 90% to 110%
+    #[serde(default)]
+    scale: f32,
+}
+
+/// Warps the strokes with a smoothed random displacement field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ElasticConfig {
+    #[serde(deserialize_with = "probability")]
+    probability: f64,
+    /// How far pixels move, in pixels
+    alpha: f32,
+    /// How smooth the displacement is, in pixels
+    sigma: f32,
+}
+
+/// Fills a random rectangle with noise
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ErasingConfig {
+    #[serde(deserialize_with = "probability")]
+    probability: f64,
+    /// Largest part of the image that's erased
+    max_area: f32,
+}
+
+/// Adds gaussian noise to every pixel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct NoiseConfig {
+    #[serde(deserialize_with = "probability")]
+    probability: f64,
+    /// Standard deviation, in `0..=255` pixel values
+    std: f32,
+}
+
+/// Rejects probabilities outside `0..=1` when the config is read, rather than once training runs
+fn probability<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let probability = f64::deserialize(deserializer)?;
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(serde::de::Error::custom(format!(
+            "`probability` must be between 0 and 1, got {probability}"
+        )));
+    }
+    Ok(probability)
+}
+
+impl AugmentationConfig {
+    pub(crate) fn init(&self, seed: u64) -> Augmenter {
+        Augmenter {
+            config: self.clone(),
+            seed,
+        }
+    }
+}
+
+/// Applies an `AugmentationConfig`
+#[derive(Debug, Clone)]
+pub(crate) struct Augmenter {
+    config: AugmentationConfig,
+    seed: u64,
+}
+
+impl Augmenter {
+    /// Augments the item at `index` of the training data for `epoch`. The random draws only
+    /// depend on those and the seed, so a run repeats whichever worker loads the item
+    pub(crate) fn apply(&self, mut item: ImageItem, epoch: usize, index: usize) -> ImageItem {
+        let config = &self.config;
+        let rng =
+            &mut StdRng::seed_from_u64(mix(mix(mix(self.seed) ^ epoch as u64) ^ index as u64));
+
+        if let Some(affine) = config
+            .affine
+            .as_ref()
+            .filter(|c| rng.random_bool(c.probability))
+        {
+            affine.apply(&mut item, rng);
+        }
+        if let Some(elastic) = config
+            .elastic
+            .as_ref()
+            .filter(|c| rng.random_bool(c.probability))
+        {
+            elastic.apply(&mut item, rng);
+        }
+        if let Some(erasing) = config
+            .erasing
+            .as_ref()
+            .filter(|c| rng.random_bool(c.probability))
+        {
+            erasing.apply(&mut item, rng);
+        }
+        if let Some(noise) = config
+            .noise
+            .as_ref()
+            .filter(|c| rng.random_bool(c.probability))
+        {
+            noise.apply(&mut item, rng);
+        }
+
+        item
+    }
+}
+
+/// SplitMix64's finalizer, so nearby seeds, epochs and indices give unrelated generators
+fn mix(value: u64) -> u64 {
+    let value = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+/// Training data with an `Augmenter` applied to every item it hands out
+pub(crate) struct AugmentedDataset<D> {
+    dataset: D,
+    augmenter: Augmenter,
+    /// How often each item was loaded. Every epoch loads each item once, so it's the epoch
+    loads: Vec<AtomicUsize>,
+}
+
+impl<D: Dataset<ImageItem>> AugmentedDataset<D> {
+    /// `first_epoch` is where a resumed run picks up, so it draws what the full run would have
+    pub(crate) fn new(dataset: D, augmenter: Augmenter, first_epoch: usize) -> Self {
+        let loads = (0..dataset.len())
+            .map(|_| AtomicUsize::new(first_epoch))
+            .collect();
+        Self {
+            dataset,
+            augmenter,
+            loads,
+        }
+    }
+}
+
+impl<D: Dataset<ImageItem>> Dataset<ImageItem> for AugmentedDataset<D> {
+    fn get(&self, index: usize) -> Option<ImageItem> {
+        let item = self.dataset.get(index)?;
+        let epoch = self.loads[index].fetch_add(1, Ordering::Relaxed);
+        Some(self.augmenter.apply(item, epoch, index))
+    }
+
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+}
+
+/// A uniform value in `-magnitude..=magnitude`
+fn symmetric(rng: &mut StdRng, magnitude: f32) -> f32 {
+    if magnitude > 0.0 {
+        rng.random_range(-magnitude..=magnitude)
+    } else {
+        0.0
+    }
+}
+
+impl AffineConfig {
+    fn apply(&self, item: &mut ImageItem, rng: &mut StdRng) {
+        let angle = symmetric(rng, self.rotation) * PI / 180.0;
+        let scale = 1.0 + symmetric(rng, self.scale);
+        let shift_x = symmetric(rng, self.shift) * item.width as f32;
+        let shift_y = symmetric(rng, self.shift) * item.height as f32;
+
+        let (sin, cos) = angle.sin_cos();
+        let center_x = (item.width as f32 - 1.0) / 2.0;
+        let center_y = (item.height as f32 - 1.0) / 2.0;
+
+        // Maps every output pixel back to where it comes from in the input
+        remap(item, |x, y| {
+            let x = (x - center_x - shift_x) / scale;
+            let y = (y - center_y - shift_y) / scale;
+            (cos * x + sin * y + center_x, -sin * x + cos * y + center_y)
+        });
+    }
+}
+
+impl ElasticConfig {
+    fn apply(&self, item: &mut ImageItem, rng: &mut StdRng) {
+        let size = item.width * item.height;
+        let field = |rng: &mut StdRng| {
+            let noise = (0..size)
+                .map(|_| rng.random_range(-1.0..=1.0))
+                .collect::<Vec<f32>>();
+            gaussian_blur(&noise, item.width, item.height, self.sigma)
+                .into_iter()
+                .map(|value| value * self.alpha)
+                .collect::<Vec<_>>()
+        };
+        let dx = field(rng);
+        let dy = field(rng);
+
+        let width = item.width;
+        remap(item, |x, y| {
+            let index = y as usize * width + x as usize;
+            (x + dx[index], y + dy[index])
+        });
+    }
+}
+
+impl ErasingConfig {
+    fn apply(&self, item: &mut ImageItem, rng: &mut StdRng) {
+        let area =
+            rng.random_range(0.02..=self.max_area.max(0.02)) * (item.width * item.height) as f32;
+        let aspect = rng.random_range(0.3f32..=3.3);
+        let height = ((area * aspect).sqrt() as usize).clamp(1, item.height);
+        let width = ((area / aspect).sqrt() as usize).clamp(1, item.width);
+        let top = rng.random_range(0..=item.height - height);
+        let left = rng.random_range(0..=item.width - width);
+
+        for y in top..top + height {
+            for x in left..left + width {
+                item.pixels[y * item.width + x] = rng.random_range(0.0..=255.0);
+            }
+        }
+    }
+}
+
+impl NoiseConfig {
+    fn apply(&self, item: &mut ImageItem, rng: &mut StdRng) {
+        for pixel in &mut item.pixels {
+            // Box-Muller, `1 - u` keeps the log away from 0
+            let u = 1.0 - rng.random::<f32>();
+            let v = rng.random::<f32>();
+            let normal = (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos();
+            *pixel = (*pixel + normal * self.std).clamp(0.0, 255.0);
+        }
+    }
+}
+
+/// Rebuilds the image by sampling the old one where `source(x, y)` points, black outside of it
+fn remap(item: &mut ImageItem, source: impl Fn(f32, f32) -> (f32, f32)) {
+    let mut pixels = vec![0.0; item.pixels.len()];
+    for y in 0..item.height {
+        for x in 0..item.width {
+            let (source_x, source_y) = source(x as f32, y as f32);
+            pixels[y * item.width + x] = bilinear(item, source_x, source_y);
+        }
+    }
+    item.pixels = pixels;
+}
+
+fn bilinear(item: &ImageItem, x: f32, y: f32) -> f32 {
+    let pixel = |x: f32, y: f32| {
+        if x < 0.0 || y < 0.0 || x >= item.width as f32 || y >= item.height as f32 {
+            0.0
+        } else {
+            item.pixels[y as usize * item.width + x as usize]
+        }
+    };
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+    let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Separable gaussian blur, edges are clamped
+fn gaussian_blur(values: &[f32], width: usize, height: usize, sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.0).ceil().max(1.0) as isize;
+    let kernel = (-radius..=radius)
+        .map(|offset| (-(offset * offset) as f32 / (2.0 * sigma * sigma).max(f32::EPSILON)).exp())
+        .collect::<Vec<_>>();
+    let total = kernel.iter().sum::<f32>();
+
+    let blur = |values: &[f32], step: [isize; 2]| {
+        let mut blurred = vec![0.0; values.len()];
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let mut sum = 0.0;
+                for (weight, offset) in kernel.iter().zip(-radius..=radius) {
+                    let sample_x = (x + offset * step[0]).clamp(0, width as isize - 1);
+                    let sample_y = (y + offset * step[1]).clamp(0, height as isize - 1);
+                    sum += weight * values[(sample_y * width as isize + sample_x) as usize];
+                }
+                blurred[(y * width as isize + x) as usize] = sum / total;
+            }
+        }
+        blurred
+    };
+
+    blur(&blur(values, [1, 0]), [0, 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::data::dataset::InMemDataset;
+
+    fn dataset(first_epoch: usize) -> AugmentedDataset<InMemDataset<ImageItem>> {
+        let config: AugmentationConfig = serde_json::from_str(
+            r#"{
+                "affine": { "probability": 0.5, "rotation": 15, "shift": 0.1 },
+                "noise": { "probability": 1.0, "std": 20 }
+            }"#,
+        )
+        .unwrap();
+        let items = (0..8)
+            .map(|label| ImageItem {
+                pixels: (0..64)
+                    .map(|pixel| ((pixel * 13 + label) % 256) as f32)
+                    .collect(),
+                width: 8,
+                height: 8,
+                label,
+            })
+            .collect();
+
+        AugmentedDataset::new(InMemDataset::new(items), config.init(42), first_epoch)
+    }
+
+    fn epoch(
+        dataset: &AugmentedDataset<InMemDataset<ImageItem>>,
+        order: &[usize],
+    ) -> Vec<Vec<f32>> {
+        let mut pixels = vec![Vec::new(); dataset.len()];
+        for &index in order {
+            pixels[index] = dataset.get(index).unwrap().pixels;
+        }
+        pixels
+    }
+
+    #[test]
+    fn items_do_not_depend_on_load_order() {
+        let forward = (0..8).collect::<Vec<_>>();
+        let shuffled = [5, 2, 7, 0, 3, 6, 1, 4];
+        let (first, second) = (dataset(0), dataset(0));
+
+        let epochs = [epoch(&first, &forward), epoch(&first, &forward)];
+        assert_eq!(epochs[0], epoch(&second, &shuffled));
+        assert_eq!(epochs[1], epoch(&second, &shuffled));
+        // Every epoch draws new changes
+        assert_ne!(epochs[0], epochs[1]);
+
+        // A run resumed after the first epoch draws what the full run did
+        assert_eq!(epochs[1], epoch(&dataset(1), &shuffled));
+    }
+
+    #[test]
+    fn probabilities_are_checked_on_load() {
+        for stage in ["affine", "elastic", "erasing", "noise"] {
+            for probability in [-0.1, 1.5] {
+                let config = format!(
+                    r#"{{ "{stage}": {{ "probability": {probability}, "alpha": 1, "sigma": 1, "max_area": 0.2, "std": 1 }} }}"#
+                );
+                let error = serde_json::from_str::<AugmentationConfig>(&config).unwrap_err();
+                assert!(
+                    error.to_string().contains("must be between 0 and 1"),
+                    "{stage}: {error}"
+                );
+            }
+        }
+
+        let config = r#"{ "noise": { "probability": 1, "std": 1 } }"#;
+        assert!(serde_json::from_str::<AugmentationConfig>(config).is_ok());
+    }
+}
@@ -1,4 +1,4 @@
-use super::Model;
+use super::{Classifier, ImageItem, Network, normalize};
 use burn::{
     data::{dataloader::batcher::Batcher, dataset::vision::MnistItem},
     prelude::*,
@@ -18,18 +18,21 @@ where
     pub(crate) targets: Tensor<B, 1, Int>,
 }
 
-const MEAN: f64 = 0.1307;
-const STD: f64 = 0.3081;
-
 impl<B: Backend> Batcher<B, MnistItem, MnistBatch<B>> for MnistBatcher {
     fn batch(&self, items: Vec<MnistItem>, device: &B::Device) -> MnistBatch<B> {
-        let images = items
+        let items = items.into_iter().map(ImageItem::from).collect();
+        Batcher::<B, ImageItem, MnistBatch<B>>::batch(self, items, device)
+    }
+}
+
+impl<B: Backend> Batcher<B, ImageItem, MnistBatch<B>> for MnistBatcher {
+    fn batch(&self, items: Vec<ImageItem>, device: &B::Device) -> MnistBatch<B> {
+        let [height, width] = [items[0].height, items[0].width];
+        let pixels = items
             .iter()
-            .map(|item| TensorData::from(item.image).convert::<B::FloatElem>())
-            .map(|data| Tensor::<B, 2>::from_data(data, device))
-            .map(|tensor| tensor.reshape([1, 28, 28]))
-            .map(|tensor| ((tensor / 255) - MEAN) / STD)
+            .flat_map(|item| item.pixels.iter().copied())
             .collect();
+        let images = normalize(pixels, [items.len(), height, width], device);
 
         let targets = items
             .iter()
@@ -38,14 +41,13 @@ impl<B: Backend> Batcher<B, MnistItem, MnistBatch<B>> for MnistBatcher {
             })
             .collect();
 
-        let images = Tensor::cat(images, 0);
         let targets = Tensor::cat(targets, 0);
 
         MnistBatch { images, targets }
     }
 }
 
-impl<B> TrainStep<MnistBatch<B>, ClassificationOutput<B>> for Model<B>
+impl<B> TrainStep<MnistBatch<B>, ClassificationOutput<B>> for Network<B>
 where
     B: AutodiffBackend,
 {
@@ -55,7 +57,7 @@ where
     }
 }
 
-impl<B> ValidStep<MnistBatch<B>, ClassificationOutput<B>> for Model<B>
+impl<B> ValidStep<MnistBatch<B>, ClassificationOutput<B>> for Network<B>
 where
     B: Backend,
 {
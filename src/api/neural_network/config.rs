@@ -1,28 +1,142 @@
-use super::Model;
+use super::{
+    ActivationConfig, ConvBlockConfig, Int8Weights, Model, QuantizationConfig, RecordFormat, legacy,
+};
 use burn::{
     config::Config,
-    nn::{DropoutConfig, LinearConfig, Relu, conv::Conv2dConfig, pool::AdaptiveAvgPool2dConfig},
+    module::Ignored,
+    nn::{DropoutConfig, LinearConfig, pool::AdaptiveAvgPool2dConfig},
     prelude::*,
+    record::RecorderError,
 };
+use std::path::PathBuf;
 
 #[derive(Debug, Config)]
 pub(crate) struct ModelConfig {
     num_classes: usize,
-    hidden_size: usize,
+    /// Sizes of the dense layers between the conv blocks and the output layer
+    hidden_sizes: Vec<usize>,
     #[config(default = "0.5")]
     dropout: f64,
+    /// Applied in order to the single channel input image
+    #[config(default = "vec![ConvBlockConfig::new(8), ConvBlockConfig::new(16)]")]
+    conv_blocks: Vec<ConvBlockConfig>,
+    /// `[height, width]` the conv output is pooled to before it's flattened
+    #[config(default = "[8, 8]")]
+    pool_size: [usize; 2],
+    #[config(default = "ActivationConfig::Relu")]
+    activation: ActivationConfig,
+    /// Adds `BatchNorm` after every convolution
+    #[config(default = false)]
+    batch_norm: bool,
+    /// Order of the layers inside the network, kept so older models load and run as trained:
+    /// - 0: the original fixed network, saved before the layers were configurable
+    /// - 1: conv -> dropout in each block, a single activation after the conv stack
+    /// - 2: conv -> norm -> activation -> dropout in each block
+    #[config(default = 2)]
+    architecture_version: u32,
 }
 
 impl ModelConfig {
+    pub(crate) fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    pub(crate) fn architecture_version(&self) -> u32 {
+        self.architecture_version
+    }
+
+    /// Overrides the number of outputs, e.g. with the classes found in a dataset
+    pub(crate) fn with_num_classes(mut self, num_classes: usize) -> Self {
+        self.num_classes = num_classes;
+        self
+    }
+
+    /// The config for weights re-saved from this one. Architecture 0 records are read into the
+    /// configurable layout, which is architecture 1 with the same layer order
+    pub(crate) fn resaved(mut self) -> Self {
+        if self.architecture_version == 0 {
+            self.architecture_version = 1;
+        }
+        self
+    }
+
+    /// Regex renames from the original network's field names (`conv1`, `conv2`, `linear1`,
+    /// `linear2`, ...) to where those layers are now, for weights saved by other tools
+    pub(crate) fn legacy_key_remapping(&self) -> Vec<(String, String)> {
+        let conv_blocks = (0..self.conv_blocks.len()).map(|index| {
+            (
+                format!(r"^conv{}\.", index + 1),
+                format!("conv_blocks.{index}.conv."),
+            )
+        });
+        // Hidden layers plus the output layer
+        let linears = (0..=self.hidden_sizes.len()).map(|index| {
+            (
+                format!(r"^linear{}\.", index + 1),
+                format!("linears.{index}."),
+            )
+        });
+
+        conv_blocks.chain(linears).collect()
+    }
+
     pub(crate) fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
+        let mut channels = 1; // 1 input channel
+        let conv_blocks = self
+            .conv_blocks
+            .iter()
+            .map(|block| {
+                let conv_block = block.init(channels, self.batch_norm, device);
+                channels = block.channels;
+                conv_block
+            })
+            .collect();
+
+        let [pool_height, pool_width] = self.pool_size;
+        let sizes = std::iter::once(channels * pool_height * pool_width)
+            .chain(self.hidden_sizes.iter().copied())
+            .chain(std::iter::once(self.num_classes))
+            .collect::<Vec<_>>();
+
         Model {
-            conv1: Conv2dConfig::new([1, 8], [3, 3]).init(device), // 1 input channel
-            conv2: Conv2dConfig::new([8, 16], [3, 3]).init(device),
-            pool: AdaptiveAvgPool2dConfig::new([8, 8]).init(),
-            activation: Relu::new(),
-            linear1: LinearConfig::new(16 * 8 * 8, self.hidden_size).init(device),
-            linear2: LinearConfig::new(self.hidden_size, self.num_classes).init(device),
+            conv_blocks,
+            pool: AdaptiveAvgPool2dConfig::new(self.pool_size).init(),
             dropout: DropoutConfig::new(self.dropout).init(),
+            linears: sizes
+                .windows(2)
+                .map(|size| LinearConfig::new(size[0], size[1]).init(device))
+                .collect(),
+            activation: self.activation.init(),
+            architecture_version: self.architecture_version,
+            activation_ranges: Ignored(Vec::new()),
+        }
+    }
+
+    /// Builds the model with the weights saved at `path` in `format`
+    pub(crate) fn init_with_file<B: Backend>(
+        &self,
+        path: impl Into<PathBuf>,
+        format: RecordFormat,
+        device: &B::Device,
+    ) -> Result<Model<B>, RecorderError> {
+        let path = path.into();
+        if self.architecture_version == 0 {
+            return legacy::load_legacy_model(self, path, format, device);
         }
+
+        let record = format.load(path, device)?;
+        Ok(self.init::<B>(device).load_record(record))
+    }
+
+    /// Builds the model with the int8 weights `quantize` saved next to `path`
+    pub(crate) fn init_with_int8_file<B: Backend>(
+        &self,
+        path: impl Into<PathBuf>,
+        quantization: &QuantizationConfig,
+        device: &B::Device,
+    ) -> Result<Model<B>, RecorderError> {
+        let weights = Int8Weights::load(&path.into().with_extension(Int8Weights::EXTENSION))?;
+        self.init::<B>(device)
+            .load_int8(&weights, quantization, device)
     }
 }
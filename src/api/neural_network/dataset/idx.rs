@@ -0,0 +1,305 @@
+use burn::data::dataset::{Dataset, InMemDataset, vision::MnistItem};
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+
+const TRAIN_IMAGES: &str = "train-images-idx3-ubyte";
+const TRAIN_LABELS: &str = "train-labels-idx1-ubyte";
+const TEST_IMAGES: &str = "t10k-images-idx3-ubyte";
+const TEST_LABELS: &str = "t10k-labels-idx1-ubyte";
+
+// Magic numbers from the IDX header: 0x08 = unsigned byte, last byte = number of dims
+const IMAGES_MAGIC: u32 = 0x0000_0803;
+const LABELS_MAGIC: u32 = 0x0000_0801;
+
+const WIDTH: usize = 28;
+const HEIGHT: usize = 28;
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum IdxError {
+    #[error("Could not find {name} (or {name}.gz) in {}", dir.display())]
+    MissingFile { dir: PathBuf, name: String },
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{} is not an IDX file (expected magic {expected:#010x}, found {found:#010x})", path.display())]
+    BadMagic {
+        path: PathBuf,
+        expected: u32,
+        found: u32,
+    },
+    #[error("{} is truncated: expected {expected} bytes of data, found {found}", path.display())]
+    Truncated {
+        path: PathBuf,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{} contains {width}x{height} images, only {WIDTH}x{HEIGHT} is supported", path.display())]
+    UnsupportedSize {
+        path: PathBuf,
+        width: usize,
+        height: usize,
+    },
+    #[error("Found {images} images but {labels} labels")]
+    CountMismatch { images: usize, labels: usize },
+}
+
+/// MNIST read from the raw IDX files on disk, either plain or gzipped.
+///
+/// Yields the same [`MnistItem`] as burn's `MnistDataset` so it can be swapped in without
+/// touching the batcher.
+pub(crate) struct MnistIdxDataset {
+    dataset: InMemDataset<MnistItem>,
+}
+
+impl Dataset<MnistItem> for MnistIdxDataset {
+    fn get(&self, index: usize) -> Option<MnistItem> {
+        self.dataset.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+}
+
+impl MnistIdxDataset {
+    pub(crate) fn train(dir: impl AsRef<Path>) -> Result<Self, IdxError> {
+        Self::new(dir.as_ref(), TRAIN_IMAGES, TRAIN_LABELS)
+    }
+
+    pub(crate) fn test(dir: impl AsRef<Path>) -> Result<Self, IdxError> {
+        Self::new(dir.as_ref(), TEST_IMAGES, TEST_LABELS)
+    }
+
+    fn new(dir: &Path, images: &str, labels: &str) -> Result<Self, IdxError> {
+        let images = read_images(&find_file(dir, images)?)?;
+        let labels = read_labels(&find_file(dir, labels)?)?;
+
+        if images.len() != labels.len() {
+            return Err(IdxError::CountMismatch {
+                images: images.len(),
+                labels: labels.len(),
+            });
+        }
+
+        let items = images
+            .into_iter()
+            .zip(labels)
+            .map(|(image, label)| MnistItem { image, label })
+            .collect();
+
+        Ok(Self {
+            dataset: InMemDataset::new(items),
+        })
+    }
+}
+
+fn find_file(dir: &Path, name: &str) -> Result<PathBuf, IdxError> {
+    [name.to_owned(), format!("{name}.gz")]
+        .into_iter()
+        .map(|file| dir.join(file))
+        .find(|path| path.is_file())
+        .ok_or_else(|| IdxError::MissingFile {
+            dir: dir.to_owned(),
+            name: name.to_owned(),
+        })
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, IdxError> {
+    let io_error = |source| IdxError::Io {
+        path: path.to_owned(),
+        source,
+    };
+
+    let file = std::fs::File::open(path).map_err(io_error)?;
+    let mut bytes = Vec::new();
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        flate2::read::GzDecoder::new(file)
+            .read_to_end(&mut bytes)
+            .map_err(io_error)?;
+    } else {
+        std::io::BufReader::new(file)
+            .read_to_end(&mut bytes)
+            .map_err(io_error)?;
+    }
+
+    Ok(bytes)
+}
+
+/// Checks the magic number and returns the dimensions and the data following the header
+fn parse_header<'a>(
+    path: &Path,
+    bytes: &'a [u8],
+    magic: u32,
+) -> Result<(Vec<usize>, &'a [u8]), IdxError> {
+    let truncated = |expected| IdxError::Truncated {
+        path: path.to_owned(),
+        expected,
+        found: bytes.len(),
+    };
+    let read_u32 = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let found = read_u32(0).ok_or_else(|| truncated(4))?;
+    if found != magic {
+        return Err(IdxError::BadMagic {
+            path: path.to_owned(),
+            expected: magic,
+            found,
+        });
+    }
+
+    let num_dims = (magic & 0xff) as usize;
+    let header_len = 4 + 4 * num_dims;
+    let dims = (0..num_dims)
+        .map(|i| read_u32(4 + 4 * i).map(|dim| dim as usize))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| truncated(header_len))?;
+
+    let data = &bytes[header_len..];
+    let expected = dims.iter().product::<usize>();
+    if data.len() < expected {
+        return Err(IdxError::Truncated {
+            path: path.to_owned(),
+            expected,
+            found: data.len(),
+        });
+    }
+
+    Ok((dims, &data[..expected]))
+}
+
+fn read_images(path: &Path) -> Result<Vec<[[f32; WIDTH]; HEIGHT]>, IdxError> {
+    let bytes = read_file(path)?;
+    let (dims, data) = parse_header(path, &bytes, IMAGES_MAGIC)?;
+
+    let (height, width) = (dims[1], dims[2]);
+    if (height, width) != (HEIGHT, WIDTH) {
+        return Err(IdxError::UnsupportedSize {
+            path: path.to_owned(),
+            width,
+            height,
+        });
+    }
+
+    // Pixels are kept in 0..=255, the batcher takes care of normalizing them
+    let images = data
+        .chunks_exact(WIDTH * HEIGHT)
+        .map(|pixels| {
+            let mut image = [[0f32; WIDTH]; HEIGHT];
+            for (i, pixel) in pixels.iter().enumerate() {
+                image[i / WIDTH][i % WIDTH] = *pixel as f32;
+            }
+            image
+        })
+        .collect();
+
+    Ok(images)
+}
+
+fn read_labels(path: &Path) -> Result<Vec<u8>, IdxError> {
+    let bytes = read_file(path)?;
+    let (_, data) = parse_header(path, &bytes, LABELS_MAGIC)?;
+
+    Ok(data.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An IDX file with `dims` in its header, followed by `data`
+    fn idx(magic: u32, dims: &[u32], data: &[u8]) -> Vec<u8> {
+        let mut bytes = magic.to_be_bytes().to_vec();
+        bytes.extend(dims.iter().flat_map(|dim| dim.to_be_bytes()));
+        bytes.extend(data);
+        bytes
+    }
+
+    fn images(count: u32) -> Vec<u8> {
+        let pixels = (0..count as usize * WIDTH * HEIGHT)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        idx(IMAGES_MAGIC, &[count, HEIGHT as u32, WIDTH as u32], &pixels)
+    }
+
+    fn labels(count: u32) -> Vec<u8> {
+        let labels = (0..count as u8).collect::<Vec<_>>();
+        idx(LABELS_MAGIC, &[count], &labels)
+    }
+
+    /// Writes the training images and labels to a fresh directory
+    fn dataset_dir(name: &str, images: &[u8], labels: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("idx-test-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(TRAIN_IMAGES), images).unwrap();
+        std::fs::write(dir.join(TRAIN_LABELS), labels).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_images_and_labels() {
+        let dir = dataset_dir("valid", &images(3), &labels(3));
+        let dataset = MnistIdxDataset::train(&dir).unwrap();
+
+        assert_eq!(dataset.len(), 3);
+        let item = dataset.get(2).unwrap();
+        assert_eq!(item.label, 2);
+        assert_eq!(item.image[0][0], (2 * WIDTH * HEIGHT % 256) as f32);
+    }
+
+    #[test]
+    fn bad_magic_is_an_error() {
+        let mut labels = labels(3);
+        labels[3] = 0x03;
+        let dir = dataset_dir("magic", &images(3), &labels);
+
+        match MnistIdxDataset::train(&dir).err() {
+            Some(IdxError::BadMagic {
+                path,
+                expected,
+                found,
+            }) => {
+                assert_eq!(path, dir.join(TRAIN_LABELS));
+                assert_eq!(expected, LABELS_MAGIC);
+                assert_eq!(found, 0x0000_0803);
+            }
+            other => panic!("expected BadMagic, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn truncated_body_is_an_error() {
+        let images = images(3);
+        let dir = dataset_dir("truncated", &images[..images.len() - 10], &labels(3));
+
+        match MnistIdxDataset::train(&dir).err() {
+            Some(IdxError::Truncated {
+                path,
+                expected,
+                found,
+            }) => {
+                assert_eq!(path, dir.join(TRAIN_IMAGES));
+                assert_eq!(expected, 3 * WIDTH * HEIGHT);
+                assert_eq!(found, 3 * WIDTH * HEIGHT - 10);
+            }
+            other => panic!("expected Truncated, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn count_mismatch_is_an_error() {
+        let dir = dataset_dir("mismatch", &images(3), &labels(2));
+
+        match MnistIdxDataset::train(&dir).err() {
+            Some(IdxError::CountMismatch { images, labels }) => {
+                assert_eq!((images, labels), (3, 2));
+            }
+            other => panic!("expected CountMismatch, got {other:?}"),
+        }
+    }
+}
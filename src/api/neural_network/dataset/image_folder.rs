@@ -0,0 +1,164 @@
+use super::{super::Preprocessor, ImageItem};
+use burn::{
+    config::Config,
+    data::dataset::{Dataset, InMemDataset},
+};
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Config)]
+pub(crate) struct ImageFolderConfig {
+    /// Root of the training images, laid out as `<train>/<class_name>/*.png`
+    pub(crate) train: String,
+    /// Root of the validation images, with the same class folders as `train`
+    pub(crate) test: String,
+    /// Every image is resized to `width` x `height`
+    #[config(default = 28)]
+    pub(crate) width: u32,
+    #[config(default = 28)]
+    pub(crate) height: u32,
+    /// Set for dark strokes on a light background
+    #[config(default = false)]
+    pub(crate) invert: bool,
+}
+
+impl ImageFolderConfig {
+    pub(crate) fn preprocessor(&self) -> Preprocessor {
+        Preprocessor::new(self.width, self.height).with_invert(self.invert)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ImageFolderError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("No class folders found in {}", root.display())]
+    NoClasses { root: PathBuf },
+    #[error("Class folder {} does not exist in the training set", path.display())]
+    UnknownClass { path: PathBuf },
+    #[error("Failed to decode {}: {source}", path.display())]
+    Decode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+/// Grayscale images read from a `root/<class_name>/*` tree.
+///
+/// Class folders are sorted by name, so the index of a class is its position in
+/// [`ImageFolderDataset::classes`].
+pub(crate) struct ImageFolderDataset {
+    dataset: InMemDataset<ImageItem>,
+    classes: Vec<String>,
+}
+
+impl Dataset<ImageItem> for ImageFolderDataset {
+    fn get(&self, index: usize) -> Option<ImageItem> {
+        self.dataset.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+}
+
+impl ImageFolderDataset {
+    /// Loads `root`, taking the classes from its sub folders
+    pub(crate) fn new(
+        root: impl AsRef<Path>,
+        preprocessor: &Preprocessor,
+    ) -> Result<Self, ImageFolderError> {
+        let root = root.as_ref();
+        Self::with_classes(root, Self::find_classes(root)?, preprocessor)
+    }
+
+    /// The class folders of `root`, without reading any images
+    pub(crate) fn find_classes(root: impl AsRef<Path>) -> Result<Vec<String>, ImageFolderError> {
+        let root = root.as_ref();
+        let classes = list_dir(root)?
+            .into_iter()
+            .filter(|path| path.is_dir())
+            .filter_map(|path| Some(path.file_name()?.to_str()?.to_owned()))
+            .collect::<Vec<_>>();
+
+        if classes.is_empty() {
+            return Err(ImageFolderError::NoClasses {
+                root: root.to_owned(),
+            });
+        }
+
+        Ok(classes)
+    }
+
+    /// Loads `root` using an existing class list, e.g. the one found in the training set
+    pub(crate) fn with_classes(
+        root: impl AsRef<Path>,
+        classes: Vec<String>,
+        preprocessor: &Preprocessor,
+    ) -> Result<Self, ImageFolderError> {
+        let root = root.as_ref();
+        let [height, width] = preprocessor.size();
+        let mut items = Vec::new();
+
+        for class_dir in list_dir(root)?.into_iter().filter(|path| path.is_dir()) {
+            let label = class_dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| classes.iter().position(|class| class == name))
+                .ok_or_else(|| ImageFolderError::UnknownClass {
+                    path: class_dir.clone(),
+                })?;
+
+            for path in list_dir(&class_dir)? {
+                // Skip anything that isn't an image, e.g. stray `.DS_Store` files
+                if !path.is_file() || ImageFormat::from_path(&path).is_err() {
+                    continue;
+                }
+
+                let pixels =
+                    preprocessor
+                        .open(&path)
+                        .map_err(|source| ImageFolderError::Decode {
+                            path: path.clone(),
+                            source,
+                        })?;
+
+                items.push(ImageItem {
+                    pixels,
+                    width,
+                    height,
+                    label,
+                });
+            }
+        }
+
+        Ok(Self {
+            dataset: InMemDataset::new(items),
+            classes,
+        })
+    }
+
+    pub(crate) fn classes(&self) -> &[String] {
+        &self.classes
+    }
+}
+
+/// Sorted entries of `dir`
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, ImageFolderError> {
+    let io_error = |source| ImageFolderError::Io {
+        path: dir.to_owned(),
+        source,
+    };
+
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    entries.sort();
+
+    Ok(entries)
+}
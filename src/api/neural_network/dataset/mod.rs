@@ -0,0 +1,27 @@
+use burn::data::dataset::vision::MnistItem;
+
+mod idx;
+mod image_folder;
+
+pub(crate) use idx::MnistIdxDataset;
+pub(crate) use image_folder::{ImageFolderConfig, ImageFolderDataset};
+
+/// A grayscale image with pixels in `0..=255`, stored row-major
+#[derive(Clone, Debug)]
+pub(crate) struct ImageItem {
+    pub(crate) pixels: Vec<f32>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) label: usize,
+}
+
+impl From<MnistItem> for ImageItem {
+    fn from(item: MnistItem) -> Self {
+        Self {
+            pixels: item.image.into_iter().flatten().collect(),
+            width: item.image[0].len(),
+            height: item.image.len(),
+            label: item.label as usize,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use burn::train::{
+    EarlyStoppingStrategy,
+    metric::store::{Aggregate, EventStoreClient, Split},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Validation metric that decides when training stops and which epoch is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StoppingMetric {
+    #[default]
+    Loss,
+    Accuracy,
+}
+
+impl StoppingMetric {
+    /// The name burn logs the metric under
+    fn name(self) -> &'static str {
+        match self {
+            StoppingMetric::Loss => "Loss",
+            StoppingMetric::Accuracy => "Accuracy",
+        }
+    }
+
+    fn improves(self, value: f64, best: f64, min_delta: f64) -> bool {
+        match self {
+            StoppingMetric::Loss => value < best - min_delta,
+            StoppingMetric::Accuracy => value > best + min_delta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct EarlyStoppingConfig {
+    #[serde(default)]
+    metric: StoppingMetric,
+    /// Epochs without improvement before training stops
+    patience: usize,
+    /// How much the metric has to improve by to count
+    #[serde(default)]
+    min_delta: f64,
+}
+
+/// The epoch with the best validation metric so far
+#[derive(Debug, Clone, Copy, Serialize)]
+pub(crate) struct BestEpoch {
+    pub(crate) epoch: usize,
+    metric: StoppingMetric,
+    pub(crate) value: f64,
+}
+
+/// Stops when the metric hasn't improved for `patience` epochs. Clones share the best epoch,
+/// so it can still be read after the learner is done with its copy.
+#[derive(Debug, Clone)]
+pub(crate) struct EarlyStopping {
+    config: EarlyStoppingConfig,
+    best: Arc<Mutex<Option<BestEpoch>>>,
+}
+
+impl EarlyStopping {
+    pub(crate) fn new(config: EarlyStoppingConfig) -> Self {
+        Self {
+            config,
+            best: Arc::default(),
+        }
+    }
+
+    pub(crate) fn patience(&self) -> usize {
+        self.config.patience
+    }
+
+    pub(crate) fn best(&self) -> Option<BestEpoch> {
+        *self
+            .best
+            .lock()
+            .expect("Best epoch lock should not be poisoned")
+    }
+}
+
+impl EarlyStoppingStrategy for EarlyStopping {
+    fn should_stop(&mut self, epoch: usize, store: &EventStoreClient) -> bool {
+        let metric = self.config.metric;
+        let Some(value) = store.find_metric(metric.name(), epoch, Aggregate::Mean, Split::Valid)
+        else {
+            return false;
+        };
+
+        let mut best = self
+            .best
+            .lock()
+            .expect("Best epoch lock should not be poisoned");
+        match *best {
+            Some(current) if !metric.improves(value, current.value, self.config.min_delta) => {
+                epoch - current.epoch >= self.config.patience
+            }
+            _ => {
+                *best = Some(BestEpoch {
+                    epoch,
+                    metric,
+                    value,
+                });
+                false
+            }
+        }
+    }
+}
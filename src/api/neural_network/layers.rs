@@ -0,0 +1,133 @@
+use burn::{
+    config::Config,
+    nn::{
+        BatchNorm, BatchNormConfig, Gelu, LeakyRelu, LeakyReluConfig, PaddingConfig2d, Relu,
+        Sigmoid, Tanh,
+        conv::{Conv2d, Conv2dConfig},
+        pool::{AvgPool2d, AvgPool2dConfig, MaxPool2d, MaxPool2dConfig},
+    },
+    prelude::*,
+};
+
+#[derive(Debug, Config)]
+pub(crate) struct ConvBlockConfig {
+    /// Number of output channels
+    pub(crate) channels: usize,
+    #[config(default = 3)]
+    pub(crate) kernel_size: usize,
+    #[config(default = 1)]
+    pub(crate) stride: usize,
+    #[config(default = 0)]
+    pub(crate) padding: usize,
+    /// Pooling applied at the end of the block
+    pub(crate) pool: Option<PoolConfig>,
+}
+
+#[derive(Debug, Config)]
+pub(crate) enum PoolConfig {
+    /// Max pooling over a square window, which is also the stride
+    Max(usize),
+    /// Average pooling over a square window, which is also the stride
+    Avg(usize),
+}
+
+#[derive(Debug, Config)]
+pub(crate) enum ActivationConfig {
+    Relu,
+    Gelu,
+    LeakyRelu,
+    Tanh,
+    Sigmoid,
+}
+
+impl ConvBlockConfig {
+    pub(crate) fn init<B: Backend>(
+        &self,
+        in_channels: usize,
+        batch_norm: bool,
+        device: &B::Device,
+    ) -> ConvBlock<B> {
+        let kernel_size = [self.kernel_size, self.kernel_size];
+        ConvBlock {
+            conv: Conv2dConfig::new([in_channels, self.channels], kernel_size)
+                .with_stride([self.stride, self.stride])
+                .with_padding(PaddingConfig2d::Explicit(self.padding, self.padding))
+                .init(device),
+            norm: batch_norm.then(|| BatchNormConfig::new(self.channels).init(device)),
+            pool: self.pool.as_ref().map(PoolConfig::init),
+        }
+    }
+}
+
+impl PoolConfig {
+    pub(crate) fn init(&self) -> Pool {
+        match *self {
+            PoolConfig::Max(size) => Pool::Max(
+                MaxPool2dConfig::new([size, size])
+                    .with_strides([size, size])
+                    .init(),
+            ),
+            PoolConfig::Avg(size) => Pool::Avg(
+                AvgPool2dConfig::new([size, size])
+                    .with_strides([size, size])
+                    .init(),
+            ),
+        }
+    }
+}
+
+impl ActivationConfig {
+    pub(crate) fn init(&self) -> Activation {
+        match self {
+            ActivationConfig::Relu => Activation::Relu(Relu::new()),
+            ActivationConfig::Gelu => Activation::Gelu(Gelu::new()),
+            ActivationConfig::LeakyRelu => Activation::LeakyRelu(LeakyReluConfig::new().init()),
+            ActivationConfig::Tanh => Activation::Tanh(Tanh::new()),
+            ActivationConfig::Sigmoid => Activation::Sigmoid(Sigmoid::new()),
+        }
+    }
+}
+
+/// A convolution with optional normalization, optionally followed by pooling
+#[derive(Debug, Module)]
+pub(crate) struct ConvBlock<B: Backend> {
+    pub(crate) conv: Conv2d<B>,
+    pub(crate) norm: Option<BatchNorm<B>>,
+    pub(crate) pool: Option<Pool>,
+}
+
+#[derive(Debug, Module, Clone)]
+pub(crate) enum Pool {
+    Max(MaxPool2d),
+    Avg(AvgPool2d),
+}
+
+impl Pool {
+    pub(crate) fn forward<B: Backend>(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        match self {
+            Pool::Max(pool) => pool.forward(x),
+            Pool::Avg(pool) => pool.forward(x),
+        }
+    }
+}
+
+#[derive(Debug, Module, Clone)]
+pub(crate) enum Activation {
+    Relu(Relu),
+    Gelu(Gelu),
+    LeakyRelu(LeakyRelu),
+    Tanh(Tanh),
+    Sigmoid(Sigmoid),
+}
+
+impl Activation {
+    pub(crate) fn forward<B: Backend, const D: usize>(&self, x: Tensor<B, D>) -> Tensor<B, D> {
+        match self {
+            Activation::Relu(activation) => activation.forward(x),
+            Activation::Gelu(activation) => activation.forward(x),
+            Activation::LeakyRelu(activation) => activation.forward(x),
+            Activation::Tanh(activation) => activation.forward(x),
+            Activation::Sigmoid(activation) => activation.forward(x),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use super::{Model, ModelConfig, RecordFormat};
+use burn::{
+    nn::{Dropout, Linear, Relu, conv::Conv2d, pool::AdaptiveAvgPool2d},
+    prelude::*,
+    record::RecorderError,
+};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// `ModelConfig` as saved before the architecture was configurable, always two conv layers
+#[derive(Debug, Deserialize)]
+pub(crate) struct LegacyModelConfig {
+    num_classes: usize,
+    hidden_size: usize,
+    dropout: f64,
+}
+
+impl From<LegacyModelConfig> for ModelConfig {
+    fn from(legacy: LegacyModelConfig) -> Self {
+        // The defaults are the layers the legacy model had hardcoded
+        ModelConfig::new(legacy.num_classes, vec![legacy.hidden_size])
+            .with_dropout(legacy.dropout)
+            .with_architecture_version(0)
+    }
+}
+
+/// The layout models were saved with before the layers were configurable (architecture 0)
+#[derive(Debug, Module)]
+struct LegacyModel<B: Backend> {
+    conv1: Conv2d<B>,
+    conv2: Conv2d<B>,
+    pool: AdaptiveAvgPool2d,
+    dropout: Dropout,
+    linear1: Linear<B>,
+    linear2: Linear<B>,
+    activation: Relu,
+}
+
+/// Reads an architecture 0 record into the equivalent configurable model
+pub(super) fn load_legacy_model<B: Backend>(
+    config: &ModelConfig,
+    path: PathBuf,
+    format: RecordFormat,
+    device: &B::Device,
+) -> Result<Model<B>, RecorderError> {
+    let record = format.load::<B, LegacyModelRecord<B>>(path, device)?;
+    let mut model = config.init::<B>(device);
+
+    let ([block1, block2], [linear1, linear2]) = (
+        model.conv_blocks.as_mut_slice(),
+        model.linears.as_mut_slice(),
+    ) else {
+        return Err(RecorderError::Unknown(
+            "Architecture 0 models have two conv blocks and one hidden layer".to_owned(),
+        ));
+    };
+
+    let legacy = LegacyModel {
+        conv1: block1.conv.clone(),
+        conv2: block2.conv.clone(),
+        pool: model.pool.clone(),
+        dropout: model.dropout.clone(),
+        linear1: linear1.clone(),
+        linear2: linear2.clone(),
+        activation: Relu::new(),
+    }
+    .load_record(record);
+
+    block1.conv = legacy.conv1;
+    block2.conv = legacy.conv2;
+    *linear1 = legacy.linear1;
+    *linear2 = legacy.linear2;
+
+    Ok(model)
+}
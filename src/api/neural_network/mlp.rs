@@ -0,0 +1,104 @@
+use super::{Activation, ActivationConfig, Classifier, RecordFormat, onnx::OnnxGraph};
+use burn::{
+    config::Config,
+    nn::{Dropout, DropoutConfig, Linear, LinearConfig},
+    prelude::*,
+    record::RecorderError,
+};
+use std::path::PathBuf;
+
+#[derive(Debug, Config)]
+pub(crate) struct MlpConfig {
+    /// Sizes of the dense layers between the flattened image and the output layer
+    #[config(default = "vec![256, 128]")]
+    hidden_sizes: Vec<usize>,
+    #[config(default = "0.2")]
+    dropout: f64,
+    #[config(default = "ActivationConfig::Relu")]
+    activation: ActivationConfig,
+}
+
+impl MlpConfig {
+    /// `input_size` is the number of pixels in an image, e.g. 28 * 28
+    pub(crate) fn init<B: Backend>(
+        &self,
+        num_classes: usize,
+        input_size: usize,
+        device: &B::Device,
+    ) -> Mlp<B> {
+        let sizes = std::iter::once(input_size)
+            .chain(self.hidden_sizes.iter().copied())
+            .chain(std::iter::once(num_classes))
+            .collect::<Vec<_>>();
+
+        Mlp {
+            linears: sizes
+                .windows(2)
+                .map(|size| LinearConfig::new(size[0], size[1]).init(device))
+                .collect(),
+            dropout: DropoutConfig::new(self.dropout).init(),
+            activation: self.activation.init(),
+        }
+    }
+
+    /// Builds the model with the weights saved at `path` in `format`
+    pub(crate) fn init_with_file<B: Backend>(
+        &self,
+        num_classes: usize,
+        input_size: usize,
+        path: impl Into<PathBuf>,
+        format: RecordFormat,
+        device: &B::Device,
+    ) -> Result<Mlp<B>, RecorderError> {
+        let record = format.load(path, device)?;
+        Ok(self
+            .init::<B>(num_classes, input_size, device)
+            .load_record(record))
+    }
+}
+
+/// Plain multilayer perceptron over the flattened image, a baseline for the CNNs
+#[derive(Debug, Module)]
+pub(crate) struct Mlp<B: Backend> {
+    // The last one outputs logits
+    linears: Vec<Linear<B>>,
+    dropout: Dropout,
+    activation: Activation,
+}
+
+impl<B: Backend> Classifier<B> for Mlp<B> {
+    fn forward(&self, images: Tensor<B, 3>) -> Tensor<B, 2> {
+        let mut x = images.flatten::<2>(1, 2);
+
+        let (output, hidden) = self
+            .linears
+            .split_last()
+            .expect("The model always has an output layer");
+        for linear in hidden {
+            x = linear.forward(x);
+            x = self.activation.forward(x);
+            x = self.dropout.forward(x);
+        }
+
+        output.forward(x)
+    }
+}
+
+impl<B: Backend> Mlp<B> {
+    /// Adds the same layers `forward` runs to `graph`, returning the logits
+    pub(crate) fn to_onnx(&self, graph: &mut OnnxGraph) -> String {
+        let images = graph.images();
+        let mut x = graph.flatten(images);
+
+        let (output, hidden) = self
+            .linears
+            .split_last()
+            .expect("The model always has an output layer");
+        for linear in hidden {
+            x = graph.linear(&x, linear);
+            x = graph.activation(&x, &self.activation);
+        }
+
+        graph.linear(&x, output)
+    }
+}
@@ -1,65 +1,161 @@
 use burn::{
-    nn::{
-        Dropout, Linear, Relu, conv::Conv2d, loss::CrossEntropyLossConfig, pool::AdaptiveAvgPool2d,
-    },
+    module::Ignored,
+    nn::{Dropout, Linear, pool::AdaptiveAvgPool2d},
     prelude::*,
-    train::ClassificationOutput,
 };
+use onnx::OnnxGraph;
 
+mod augment;
 mod batch;
 mod config;
+mod dataset;
+mod early_stopping;
+mod layers;
+mod legacy;
+mod mlp;
+mod network;
+mod onnx;
+mod optimizer;
+mod preprocess;
+mod prune;
+mod quantize;
+mod record;
+mod resnet;
+mod safetensors;
+mod scheduler;
 
+pub(crate) use augment::{AugmentationConfig, AugmentedDataset};
 pub(crate) use batch::{MnistBatch, MnistBatcher};
 pub(crate) use config::ModelConfig;
+pub(crate) use dataset::{ImageFolderConfig, ImageFolderDataset, ImageItem, MnistIdxDataset};
+pub(crate) use early_stopping::{EarlyStopping, EarlyStoppingConfig};
+pub(crate) use layers::{Activation, ActivationConfig, ConvBlock, ConvBlockConfig};
+pub(crate) use legacy::LegacyModelConfig;
+pub(crate) use mlp::{Mlp, MlpConfig};
+pub(crate) use network::{Architecture, Classifier, Network};
+pub(crate) use onnx::{read_initializers, read_input_major_weights};
+pub(crate) use optimizer::OptimizerConfig;
+pub(crate) use preprocess::{PreprocessMode, Preprocessor, normalize};
+pub(crate) use prune::{LayerSparsity, MaskedOptimizer, PruneMask};
+pub(crate) use quantize::{ActivationRange, Granularity, Int8Weights, QuantizationConfig};
+pub(crate) use record::RecordFormat;
+pub(crate) use resnet::{ResNet, ResNetConfig};
+pub(crate) use safetensors::{StoredTensor, StoredValues, encode_safetensors};
+pub(crate) use scheduler::{Scheduler, SchedulerConfig};
 
 #[derive(Debug, Module)]
 pub(crate) struct Model<B: Backend> {
-    // Convolutional layers - input image, extract features
-    conv1: Conv2d<B>,
-    conv2: Conv2d<B>,
-    // Resize image into chunks (shown as 8 x 8 chunks in `init` below)
+    // Convolutional blocks - input image, extract features
+    conv_blocks: Vec<ConvBlock<B>>,
+    // Resize the features into chunks (`pool_size` in the config)
     pool: AdaptiveAvgPool2d,
     // Randomly zeroes neurons during training to prevent overfitting
     dropout: Dropout,
-    // Connected layers - input features, output logits
-    linear1: Linear<B>,
-    linear2: Linear<B>,
+    // Connected layers - input features, the last one outputs logits
+    linears: Vec<Linear<B>>,
     // Activation fn
-    activation: Relu,
+    activation: Activation,
+    // Layer order, see `ModelConfig`
+    architecture_version: u32,
+    // Input ranges the layers round to once quantized, empty for float models
+    activation_ranges: Ignored<Vec<ActivationRange>>,
 }
 
-impl<B> Model<B>
+impl<B> Classifier<B> for Model<B>
 where
     B: Backend,
 {
-    pub(crate) fn forward_classification(
+    fn forward(&self, images: Tensor<B, 3>) -> Tensor<B, 2> {
+        self.forward_with(images, None)
+    }
+}
+
+impl<B: Backend> Model<B> {
+    /// `forward`, widening `calibration` to the input of every conv and linear layer when given
+    fn forward_with(
         &self,
         images: Tensor<B, 3>,
-        targets: Tensor<B, 1, Int>,
-    ) -> ClassificationOutput<B> {
-        let output = self.forward(images);
-        let loss = CrossEntropyLossConfig::new()
-            .init(&output.device())
-            .forward(output.clone(), targets.clone());
+        mut calibration: Option<&mut Vec<ActivationRange>>,
+    ) -> Tensor<B, 2> {
+        let [batch_size, height, width] = images.dims();
+        let mut x = images.reshape([batch_size, 1, height, width]);
+        let mut layer = 0;
+
+        // Before architecture 2 the activation only ran once, after the whole conv stack
+        let legacy_order = self.architecture_version < 2;
+        for block in &self.conv_blocks {
+            x = self.layer_input(layer, x, calibration.as_deref_mut());
+            layer += 1;
+            x = block.conv.forward(x);
+            if let Some(norm) = &block.norm {
+                x = norm.forward(x);
+            }
+            if !legacy_order {
+                x = self.activation.forward(x);
+            }
+            x = self.dropout.forward(x);
+            if let Some(pool) = &block.pool {
+                x = pool.forward(x);
+            }
+        }
+        if legacy_order {
+            x = self.activation.forward(x);
+        }
+
+        // Flattened size is `channels * pool_size`, worked out when the layers are built
+        let x = self.pool.forward(x);
+        let mut x = x.flatten::<2>(1, 3);
+
+        let (output, hidden) = self
+            .linears
+            .split_last()
+            .expect("The model always has an output layer");
+        for linear in hidden {
+            x = self.layer_input(layer, x, calibration.as_deref_mut());
+            layer += 1;
+            x = linear.forward(x);
+            x = self.dropout.forward(x);
+            x = self.activation.forward(x);
+        }
 
-        ClassificationOutput::new(loss, output, targets)
+        let x = self.layer_input(layer, x, calibration);
+        output.forward(x)
     }
 
-    pub(crate) fn forward(&self, images: Tensor<B, 3>) -> Tensor<B, 2> {
-        let [batch_size, height, width] = images.dims();
-        let x = images.reshape([batch_size, 1, height, width]);
-        let x = self.conv1.forward(x);
-        let x = self.dropout.forward(x);
-        let x = self.conv2.forward(x);
-        let x = self.dropout.forward(x);
-        let x = self.activation.forward(x);
+    /// Adds the same layers `forward` runs to `graph`, returning the logits.
+    /// Dropout does nothing outside of training, so it's left out
+    fn to_onnx(&self, graph: &mut OnnxGraph) -> String {
+        let mut x = graph.images();
 
-        let x = self.pool.forward(x);
-        let x = x.reshape([batch_size, 16 * 8 * 8]);
-        let x = self.linear1.forward(x);
-        let x = self.dropout.forward(x);
-        let x = self.activation.forward(x);
+        let legacy_order = self.architecture_version < 2;
+        for block in &self.conv_blocks {
+            x = graph.conv2d(x, &block.conv);
+            if let Some(norm) = &block.norm {
+                x = graph.batch_norm(x, norm);
+            }
+            if !legacy_order {
+                x.name = graph.activation(&x.name, &self.activation);
+            }
+            if let Some(pool) = &block.pool {
+                x = graph.pool(x, pool);
+            }
+        }
+        if legacy_order {
+            x.name = graph.activation(&x.name, &self.activation);
+        }
+
+        let x = graph.adaptive_avg_pool(x, &self.pool);
+        let mut x = graph.flatten(x);
+
+        let (output, hidden) = self
+            .linears
+            .split_last()
+            .expect("The model always has an output layer");
+        for linear in hidden {
+            x = graph.linear(&x, linear);
+            x = graph.activation(&x, &self.activation);
+        }
 
-        self.linear2.forward(x)
+        graph.linear(&x, output)
     }
 }
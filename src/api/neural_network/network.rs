@@ -0,0 +1,103 @@
+use super::{Mlp, Model, RecordFormat, ResNet, onnx::OnnxGraph};
+use burn::{
+    nn::loss::CrossEntropyLossConfig, prelude::*, record::RecorderError,
+    train::ClassificationOutput,
+};
+use burn_store::{ApplyResult, ModuleSnapshot, ModuleStore};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Shared by every model family: normalized `[batch, height, width]` images in, logits out
+pub(crate) trait Classifier<B: Backend> {
+    fn forward(&self, images: Tensor<B, 3>) -> Tensor<B, 2>;
+
+    fn forward_classification(
+        &self,
+        images: Tensor<B, 3>,
+        targets: Tensor<B, 1, Int>,
+    ) -> ClassificationOutput<B> {
+        let output = self.forward(images);
+        let loss = CrossEntropyLossConfig::new()
+            .init(&output.device())
+            .forward(output.clone(), targets.clone());
+
+        ClassificationOutput::new(loss, output, targets)
+    }
+}
+
+/// Which model family is trained, picked with `architecture` in the training config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Architecture {
+    /// `Model`, described by the `model` config section
+    #[default]
+    Cnn,
+    /// `ResNet`, described by the `resnet` config section
+    ResNet,
+    /// `Mlp`, described by the `mlp` config section. A baseline to compare the others with
+    Mlp,
+}
+
+/// Any of the model families, so training and inference don't depend on the architecture
+#[derive(Debug, Module)]
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Network<B: Backend> {
+    Cnn(Model<B>),
+    ResNet(ResNet<B>),
+    Mlp(Mlp<B>),
+}
+
+impl<B: Backend> Network<B> {
+    /// Saves the weights of the wrapped model only, so CNN files stay readable as a plain `Model`
+    pub(crate) fn save_weights(
+        self,
+        path: impl Into<PathBuf>,
+        format: RecordFormat,
+    ) -> Result<(), RecorderError> {
+        match self {
+            Network::Cnn(model) => format.save(model, path),
+            Network::ResNet(resnet) => format.save(resnet, path),
+            Network::Mlp(mlp) => format.save(mlp, path),
+        }
+    }
+
+    /// Loads the tensors in `store` into the wrapped model, matched against the same paths
+    /// `save_weights` uses
+    pub(crate) fn load_from<S: ModuleStore>(
+        &mut self,
+        store: &mut S,
+    ) -> Result<ApplyResult, S::Error> {
+        match self {
+            Network::Cnn(model) => model.load_from(store),
+            Network::ResNet(resnet) => resnet.load_from(store),
+            Network::Mlp(mlp) => mlp.load_from(store),
+        }
+    }
+
+    /// The forward pass as a serialized ONNX model, over normalized `[batch, height, width]`
+    /// images of `input_size`. `metadata` is stored in the model's `metadata_props`
+    pub(crate) fn to_onnx(
+        &self,
+        input_size: [usize; 2],
+        num_classes: usize,
+        metadata: &[(&str, String)],
+    ) -> Vec<u8> {
+        let mut graph = OnnxGraph::new(input_size);
+        let logits = match self {
+            Network::Cnn(model) => model.to_onnx(&mut graph),
+            Network::ResNet(resnet) => resnet.to_onnx(&mut graph),
+            Network::Mlp(mlp) => mlp.to_onnx(&mut graph),
+        };
+        graph.encode(&logits, num_classes, metadata)
+    }
+}
+
+impl<B: Backend> Classifier<B> for Network<B> {
+    fn forward(&self, images: Tensor<B, 3>) -> Tensor<B, 2> {
+        match self {
+            Network::Cnn(model) => model.forward(images),
+            Network::ResNet(resnet) => resnet.forward(images),
+            Network::Mlp(mlp) => mlp.forward(images),
+        }
+    }
+}
@@ -0,0 +1,953 @@
+use super::{Activation, layers::Pool};
+use burn::{
+    nn::{
+        BatchNorm, Linear, PaddingConfig2d,
+        conv::Conv2d,
+        pool::{AdaptiveAvgPool2d, AvgPool2d, MaxPool2d},
+    },
+    prelude::*,
+};
+use std::collections::BTreeSet;
+
+// Opset 17 and IR version 8 are read by every current ONNX runtime
+const OPSET_VERSION: i64 = 17;
+const IR_VERSION: i64 = 8;
+
+// `TensorProto.DataType` values
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+const DOUBLE: i64 = 11;
+
+/// Name of the graph's input, normalized `[batch, height, width]` images
+pub(crate) const INPUT: &str = "images";
+/// Name of the graph's output, `[batch, num_classes]` logits
+pub(crate) const OUTPUT: &str = "logits";
+
+/// Builds an ONNX graph layer by layer, written out as a `ModelProto`.
+///
+/// Only the handful of operators the models use are covered, so no protobuf crate is needed.
+pub(crate) struct OnnxGraph {
+    input_size: [usize; 2],
+    nodes: Vec<Message>,
+    initializers: Vec<Message>,
+    // Keeps node and weight names unique
+    count: usize,
+}
+
+/// An intermediate `[batch, channels, height, width]` value, with its size so pooling can be
+/// worked out ahead of time
+#[derive(Clone)]
+pub(crate) struct FeatureMap {
+    pub(crate) name: String,
+    pub(crate) size: [usize; 2],
+}
+
+impl OnnxGraph {
+    /// `input_size` is the `[height, width]` of the images the model is trained on
+    pub(crate) fn new(input_size: [usize; 2]) -> Self {
+        Self {
+            input_size,
+            nodes: Vec::new(),
+            initializers: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// The input images with a single channel added
+    pub(crate) fn images(&mut self) -> FeatureMap {
+        let [height, width] = self.input_size;
+        let shape = self.int64s("shape", &[-1, 1, height as i64, width as i64]);
+        FeatureMap {
+            name: self.node("Reshape", &[INPUT, &shape], Vec::new()),
+            size: self.input_size,
+        }
+    }
+
+    pub(crate) fn conv2d<B: Backend>(&mut self, x: FeatureMap, conv: &Conv2d<B>) -> FeatureMap {
+        let padding = padding(&conv.padding, conv.kernel_size);
+        let weight = self.tensor("conv.weight", conv.weight.val());
+        let mut inputs = vec![x.name.as_str(), &weight];
+        let bias = conv
+            .bias
+            .as_ref()
+            .map(|bias| self.tensor("conv.bias", bias.val()));
+        inputs.extend(bias.as_deref());
+
+        let name = self.node(
+            "Conv",
+            &inputs,
+            vec![
+                Attribute::ints("kernel_shape", conv.kernel_size),
+                Attribute::ints("strides", conv.stride),
+                Attribute::ints("pads", [padding[0], padding[1], padding[0], padding[1]]),
+                Attribute::ints("dilations", conv.dilation),
+                Attribute::int("group", conv.groups as i64),
+            ],
+        );
+
+        FeatureMap {
+            name,
+            size: output_size(
+                x.size,
+                conv.kernel_size,
+                conv.stride,
+                padding,
+                conv.dilation,
+            ),
+        }
+    }
+
+    /// Uses the running statistics, like burn does outside of training
+    pub(crate) fn batch_norm<B: Backend>(
+        &mut self,
+        x: FeatureMap,
+        norm: &BatchNorm<B>,
+    ) -> FeatureMap {
+        let scale = self.tensor("norm.gamma", norm.gamma.val());
+        let bias = self.tensor("norm.beta", norm.beta.val());
+        let mean = self.tensor("norm.running_mean", norm.running_mean.value());
+        let var = self.tensor("norm.running_var", norm.running_var.value());
+
+        FeatureMap {
+            name: self.node(
+                "BatchNormalization",
+                &[&x.name, &scale, &bias, &mean, &var],
+                vec![Attribute::float("epsilon", norm.epsilon as f32)],
+            ),
+            size: x.size,
+        }
+    }
+
+    pub(crate) fn pool(&mut self, x: FeatureMap, pool: &Pool) -> FeatureMap {
+        match pool {
+            Pool::Max(pool) => self.max_pool(x, pool),
+            Pool::Avg(pool) => self.avg_pool(x, pool),
+        }
+    }
+
+    fn max_pool(&mut self, x: FeatureMap, pool: &MaxPool2d) -> FeatureMap {
+        let padding = padding(&pool.padding, pool.kernel_size);
+        FeatureMap {
+            name: self.node(
+                "MaxPool",
+                &[&x.name],
+                vec![
+                    Attribute::ints("kernel_shape", pool.kernel_size),
+                    Attribute::ints("strides", pool.stride),
+                    Attribute::ints("pads", [padding[0], padding[1], padding[0], padding[1]]),
+                    Attribute::ints("dilations", pool.dilation),
+                ],
+            ),
+            size: output_size(
+                x.size,
+                pool.kernel_size,
+                pool.stride,
+                padding,
+                pool.dilation,
+            ),
+        }
+    }
+
+    fn avg_pool(&mut self, x: FeatureMap, pool: &AvgPool2d) -> FeatureMap {
+        let padding = padding(&pool.padding, pool.kernel_size);
+        FeatureMap {
+            name: self.node(
+                "AveragePool",
+                &[&x.name],
+                vec![
+                    Attribute::ints("kernel_shape", pool.kernel_size),
+                    Attribute::ints("strides", pool.stride),
+                    Attribute::ints("pads", [padding[0], padding[1], padding[0], padding[1]]),
+                    Attribute::int("count_include_pad", pool.count_include_pad as i64),
+                ],
+            ),
+            size: output_size(x.size, pool.kernel_size, pool.stride, padding, [1, 1]),
+        }
+    }
+
+    /// ONNX has no adaptive pooling, so the averaging is done as two matrix products with the
+    /// same windows burn uses, which also covers sizes that don't divide evenly
+    pub(crate) fn adaptive_avg_pool(
+        &mut self,
+        x: FeatureMap,
+        pool: &AdaptiveAvgPool2d,
+    ) -> FeatureMap {
+        let [height, width] = x.size;
+        let [out_height, out_width] = pool.output_size;
+
+        // `[out_height, height]` on the left averages the rows
+        let rows = self.initializer(
+            "pool.rows",
+            &[out_height, height],
+            &averaging_matrix(height, out_height),
+        );
+        let x = self.node("MatMul", &[&rows, &x.name], Vec::new());
+
+        // `[width, out_width]` on the right averages the columns
+        let columns = transpose(&averaging_matrix(width, out_width), out_width, width);
+        let columns = self.initializer("pool.columns", &[width, out_width], &columns);
+
+        FeatureMap {
+            name: self.node("MatMul", &[&x, &columns], Vec::new()),
+            size: pool.output_size,
+        }
+    }
+
+    /// `[batch, channels * height * width]`, in the order burn's `flatten` uses
+    pub(crate) fn flatten(&mut self, x: FeatureMap) -> String {
+        self.node("Flatten", &[&x.name], vec![Attribute::int("axis", 1)])
+    }
+
+    pub(crate) fn linear<B: Backend>(&mut self, x: &str, linear: &Linear<B>) -> String {
+        // burn stores the weight as `[inputs, outputs]`, which is what Gemm expects untransposed
+        let weight = self.tensor("linear.weight", linear.weight.val());
+        let mut inputs = vec![x, &weight];
+        let bias = linear
+            .bias
+            .as_ref()
+            .map(|bias| self.tensor("linear.bias", bias.val()));
+        inputs.extend(bias.as_deref());
+
+        self.node("Gemm", &inputs, Vec::new())
+    }
+
+    pub(crate) fn add(&mut self, left: &str, right: &str) -> String {
+        self.node("Add", &[left, right], Vec::new())
+    }
+
+    pub(crate) fn relu(&mut self, x: &str) -> String {
+        self.node("Relu", &[x], Vec::new())
+    }
+
+    pub(crate) fn activation(&mut self, x: &str, activation: &Activation) -> String {
+        match activation {
+            Activation::Relu(_) => self.relu(x),
+            Activation::Gelu(_) => self.gelu(x),
+            Activation::LeakyRelu(activation) => self.node(
+                "LeakyRelu",
+                &[x],
+                vec![Attribute::float("alpha", activation.negative_slope as f32)],
+            ),
+            Activation::Tanh(_) => self.node("Tanh", &[x], Vec::new()),
+            Activation::Sigmoid(_) => self.node("Sigmoid", &[x], Vec::new()),
+        }
+    }
+
+    /// `x * (1 + erf(x / sqrt(2))) / 2`, the exact GELU burn uses. Opset 17 has no Gelu operator
+    fn gelu(&mut self, x: &str) -> String {
+        let sqrt_2 = self.initializer("sqrt_2", &[], &[std::f32::consts::SQRT_2]);
+        let one = self.initializer("one", &[], &[1.0]);
+        let half = self.initializer("half", &[], &[0.5]);
+
+        let scaled = self.node("Div", &[x, &sqrt_2], Vec::new());
+        let erf = self.node("Erf", &[&scaled], Vec::new());
+        let shifted = self.node("Add", &[&erf, &one], Vec::new());
+        let product = self.node("Mul", &[x, &shifted], Vec::new());
+        self.node("Mul", &[&product, &half], Vec::new())
+    }
+
+    /// Serializes the graph with `output` as the logits, `metadata` ends up in `metadata_props`
+    pub(crate) fn encode(
+        mut self,
+        output: &str,
+        num_classes: usize,
+        metadata: &[(&str, String)],
+    ) -> Vec<u8> {
+        // Renamed so the output has a stable name
+        self.nodes.push(
+            Message::default()
+                .string(1, output)
+                .string(2, OUTPUT)
+                .string(3, OUTPUT)
+                .string(4, "Identity"),
+        );
+
+        let [height, width] = self.input_size;
+        let input = value_info(
+            INPUT,
+            &[Dimension::Named("batch"), height.into(), width.into()],
+        );
+        let output = value_info(OUTPUT, &[Dimension::Named("batch"), num_classes.into()]);
+
+        let mut graph = Message::default().string(2, "classifier");
+        for node in self.nodes {
+            graph = graph.message(1, node);
+        }
+        for initializer in self.initializers {
+            graph = graph.message(5, initializer);
+        }
+        graph = graph.message(11, input).message(12, output);
+
+        let mut model = Message::default()
+            .int(1, IR_VERSION)
+            .string(2, env!("CARGO_PKG_NAME"))
+            .string(3, env!("CARGO_PKG_VERSION"))
+            .message(7, graph)
+            .message(8, Message::default().string(1, "").int(2, OPSET_VERSION));
+        for (key, value) in metadata {
+            model = model.message(14, Message::default().string(1, key).string(2, value));
+        }
+
+        model.0
+    }
+
+    fn node(&mut self, op_type: &str, inputs: &[&str], attributes: Vec<Attribute>) -> String {
+        let name = self.unique(op_type);
+
+        let mut node = Message::default();
+        for input in inputs {
+            node = node.string(1, input);
+        }
+        node = node.string(2, &name).string(3, &name).string(4, op_type);
+        for attribute in attributes {
+            node = node.message(5, attribute.encode());
+        }
+
+        self.nodes.push(node);
+        name
+    }
+
+    fn tensor<const D: usize>(&mut self, name: &str, tensor: Tensor<impl Backend, D>) -> String {
+        let dims = tensor.dims();
+        let values = tensor.into_data().iter::<f32>().collect::<Vec<_>>();
+        self.initializer(name, &dims, &values)
+    }
+
+    fn initializer(&mut self, name: &str, dims: &[usize], values: &[f32]) -> String {
+        let name = self.unique(name);
+        let raw = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.push_initializer(&name, dims, FLOAT, &raw);
+        name
+    }
+
+    fn int64s(&mut self, name: &str, values: &[i64]) -> String {
+        let name = self.unique(name);
+        let raw = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.push_initializer(&name, &[values.len()], INT64, &raw);
+        name
+    }
+
+    fn push_initializer(&mut self, name: &str, dims: &[usize], data_type: i64, raw: &[u8]) {
+        let mut tensor = Message::default();
+        for &dim in dims {
+            tensor = tensor.int(1, dim as i64);
+        }
+        tensor = tensor.int(2, data_type).string(8, name).bytes(9, raw);
+        self.initializers.push(tensor);
+    }
+
+    fn unique(&mut self, name: &str) -> String {
+        self.count += 1;
+        format!("{name}_{}", self.count)
+    }
+}
+
+/// `[height, width]` padding on each side
+fn padding(config: &PaddingConfig2d, kernel_size: [usize; 2]) -> [usize; 2] {
+    match *config {
+        PaddingConfig2d::Valid => [0, 0],
+        PaddingConfig2d::Explicit(height, width) => [height, width],
+        // Only keeps the size for odd kernels with stride 1, the only case burn supports
+        PaddingConfig2d::Same => kernel_size.map(|size| (size - 1) / 2),
+    }
+}
+
+fn output_size(
+    size: [usize; 2],
+    kernel_size: [usize; 2],
+    stride: [usize; 2],
+    padding: [usize; 2],
+    dilation: [usize; 2],
+) -> [usize; 2] {
+    std::array::from_fn(|axis| {
+        (size[axis] + 2 * padding[axis] - dilation[axis] * (kernel_size[axis] - 1) - 1)
+            / stride[axis]
+            + 1
+    })
+}
+
+/// `[output, input]` weights averaging each of burn's adaptive pooling windows
+fn averaging_matrix(input: usize, output: usize) -> Vec<f32> {
+    let mut matrix = vec![0.0; output * input];
+    for row in 0..output {
+        let start = row * input / output;
+        let end = ((row + 1) * input).div_ceil(output);
+        for column in start..end {
+            matrix[row * input + column] = 1.0 / (end - start) as f32;
+        }
+    }
+    matrix
+}
+
+fn transpose(matrix: &[f32], rows: usize, columns: usize) -> Vec<f32> {
+    (0..columns * rows)
+        .map(|index| matrix[(index % rows) * columns + index / rows])
+        .collect()
+}
+
+enum Dimension {
+    Fixed(usize),
+    Named(&'static str),
+}
+
+impl From<usize> for Dimension {
+    fn from(size: usize) -> Self {
+        Dimension::Fixed(size)
+    }
+}
+
+/// A float tensor's `ValueInfoProto`
+fn value_info(name: &str, dims: &[Dimension]) -> Message {
+    let mut shape = Message::default();
+    for dim in dims {
+        shape = shape.message(
+            1,
+            match dim {
+                Dimension::Fixed(size) => Message::default().int(1, *size as i64),
+                Dimension::Named(name) => Message::default().string(2, name),
+            },
+        );
+    }
+
+    let tensor_type = Message::default().int(1, FLOAT).message(2, shape);
+    Message::default()
+        .string(1, name)
+        .message(2, Message::default().message(1, tensor_type))
+}
+
+enum Attribute {
+    Int(&'static str, i64),
+    Ints(&'static str, Vec<i64>),
+    Float(&'static str, f32),
+}
+
+impl Attribute {
+    fn int(name: &'static str, value: i64) -> Self {
+        Attribute::Int(name, value)
+    }
+
+    fn ints<const N: usize>(name: &'static str, values: [usize; N]) -> Self {
+        Attribute::Ints(name, values.iter().map(|&value| value as i64).collect())
+    }
+
+    fn float(name: &'static str, value: f32) -> Self {
+        Attribute::Float(name, value)
+    }
+
+    // Field numbers and `AttributeType` values from onnx.proto
+    fn encode(self) -> Message {
+        match self {
+            Attribute::Int(name, value) => {
+                Message::default().string(1, name).int(3, value).int(20, 2)
+            }
+            Attribute::Ints(name, values) => {
+                let mut attribute = Message::default().string(1, name);
+                for value in values {
+                    attribute = attribute.int(8, value);
+                }
+                attribute.int(20, 7)
+            }
+            Attribute::Float(name, value) => Message::default()
+                .string(1, name)
+                .float(2, value)
+                .int(20, 1),
+        }
+    }
+}
+
+/// An encoded protobuf message. Repeated fields are written one entry at a time, which every
+/// parser accepts
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    // Protobuf wire types
+    const VARINT: u64 = 0;
+    const FIXED64: u64 = 1;
+    const LENGTH_DELIMITED: u64 = 2;
+    const FIXED32: u64 = 5;
+
+    fn int(mut self, field: u64, value: i64) -> Self {
+        self.tag(field, Self::VARINT);
+        // Negative numbers take all ten bytes, like protobuf's own encoder
+        self.varint(value as u64);
+        self
+    }
+
+    fn float(mut self, field: u64, value: f32) -> Self {
+        self.tag(field, Self::FIXED32);
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+
+    fn bytes(mut self, field: u64, bytes: &[u8]) -> Self {
+        self.tag(field, Self::LENGTH_DELIMITED);
+        self.varint(bytes.len() as u64);
+        self.0.extend(bytes);
+        self
+    }
+
+    fn string(self, field: u64, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    fn message(self, field: u64, message: Message) -> Self {
+        self.bytes(field, &message.0)
+    }
+
+    fn tag(&mut self, field: u64, wire_type: u64) {
+        self.varint(field << 3 | wire_type);
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
+
+/// A float weight stored in an ONNX model
+pub(crate) struct OnnxTensor {
+    pub(crate) name: String,
+    pub(crate) shape: Vec<usize>,
+    pub(crate) values: Vec<f32>,
+}
+
+impl OnnxTensor {
+    /// The matrix with its rows and columns swapped, anything else unchanged
+    pub(crate) fn transposed(self) -> Self {
+        let &[rows, columns] = self.shape.as_slice() else {
+            return self;
+        };
+        Self {
+            name: self.name,
+            shape: vec![columns, rows],
+            values: transpose(&self.values, rows, columns),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum OnnxError {
+    #[error("Not a valid ONNX model: {0}")]
+    Malformed(&'static str),
+    #[error("{0} keeps its data in an external file, which isn't supported")]
+    ExternalData(String),
+}
+
+/// Reads the float initializers of an ONNX model's graph, which is where exporters put the
+/// weights. Integer initializers like reshape targets are left out
+pub(crate) fn read_initializers(bytes: &[u8]) -> Result<Vec<OnnxTensor>, OnnxError> {
+    let mut tensors = Vec::new();
+
+    for field in Fields(bytes) {
+        let (7, Value::Bytes(graph)) = field? else {
+            continue;
+        };
+        for field in Fields(graph) {
+            let (5, Value::Bytes(tensor)) = field? else {
+                continue;
+            };
+            tensors.extend(read_tensor(tensor)?);
+        }
+    }
+
+    Ok(tensors)
+}
+
+/// Names of the initializers a node multiplies with as `[inputs, outputs]` matrices: Gemm's `B`
+/// without `transB`, which is how `export` writes linear layers, and MatMul's second input.
+/// PyTorch exports linear layers as Gemm with `transB = 1`, keeping its `[outputs, inputs]`
+pub(crate) fn read_input_major_weights(bytes: &[u8]) -> Result<BTreeSet<String>, OnnxError> {
+    let mut names = BTreeSet::new();
+
+    for field in Fields(bytes) {
+        let (7, Value::Bytes(graph)) = field? else {
+            continue;
+        };
+        for field in Fields(graph) {
+            let (1, Value::Bytes(node)) = field? else {
+                continue;
+            };
+            let node = read_node(node)?;
+            let input_major = match node.op_type.as_str() {
+                "Gemm" => !node.trans_b,
+                "MatMul" => true,
+                _ => false,
+            };
+            if input_major {
+                names.extend(node.inputs.into_iter().nth(1));
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+struct Node {
+    op_type: String,
+    inputs: Vec<String>,
+    trans_b: bool,
+}
+
+// Field numbers from `NodeProto` and `AttributeProto` in onnx.proto
+fn read_node(bytes: &[u8]) -> Result<Node, OnnxError> {
+    let mut node = Node {
+        op_type: String::new(),
+        inputs: Vec::new(),
+        trans_b: false,
+    };
+
+    for field in Fields(bytes) {
+        match field? {
+            (1, Value::Bytes(input)) => node
+                .inputs
+                .push(String::from_utf8_lossy(input).into_owned()),
+            (4, Value::Bytes(op_type)) => {
+                node.op_type = String::from_utf8_lossy(op_type).into_owned()
+            }
+            (5, Value::Bytes(attribute)) => {
+                let (mut name, mut value) = (&[][..], 0);
+                for field in Fields(attribute) {
+                    match field? {
+                        (1, Value::Bytes(bytes)) => name = bytes,
+                        (3, Value::Varint(int)) => value = int,
+                        _ => {}
+                    }
+                }
+                if name == b"transB" {
+                    node.trans_b = value != 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(node)
+}
+
+// Field numbers from `TensorProto` in onnx.proto
+fn read_tensor(bytes: &[u8]) -> Result<Option<OnnxTensor>, OnnxError> {
+    let mut name = String::new();
+    let mut shape = Vec::new();
+    let mut data_type = 0;
+    let mut raw = None;
+    let mut values = Vec::new();
+    let mut external = false;
+
+    for field in Fields(bytes) {
+        match field? {
+            (1, Value::Varint(dim)) => shape.push(dim as usize),
+            (1, Value::Bytes(packed)) => {
+                for dim in Fields::varints(packed) {
+                    shape.push(dim? as usize);
+                }
+            }
+            (2, Value::Varint(value)) => data_type = value as i64,
+            (4, Value::Fixed32(value)) => values.push(f32::from_le_bytes(value)),
+            (4, Value::Bytes(packed)) => values.extend(
+                packed
+                    .chunks_exact(4)
+                    .map(|value| f32::from_le_bytes(value.try_into().unwrap())),
+            ),
+            (10, Value::Fixed64(value)) => values.push(f64::from_le_bytes(value) as f32),
+            (10, Value::Bytes(packed)) => values.extend(
+                packed
+                    .chunks_exact(8)
+                    .map(|value| f64::from_le_bytes(value.try_into().unwrap()) as f32),
+            ),
+            (8, Value::Bytes(value)) => name = String::from_utf8_lossy(value).into_owned(),
+            (9, Value::Bytes(value)) => raw = Some(value),
+            // `external_data` entries, then `data_location`, where 1 is `EXTERNAL`
+            (13, Value::Bytes(_)) => external = true,
+            (14, Value::Varint(location)) => external |= location == 1,
+            _ => {}
+        }
+    }
+
+    if external {
+        return Err(OnnxError::ExternalData(name));
+    }
+
+    // `raw_data` takes precedence over the typed fields when set
+    let values = match (data_type, raw) {
+        (FLOAT, Some(raw)) => raw
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+            .collect(),
+        (DOUBLE, Some(raw)) => raw
+            .chunks_exact(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap()) as f32)
+            .collect(),
+        (FLOAT | DOUBLE, None) => values,
+        _ => return Ok(None),
+    };
+
+    if values.len() != shape.iter().product::<usize>() {
+        return Err(OnnxError::Malformed(
+            "a tensor's data doesn't match its shape",
+        ));
+    }
+
+    Ok(Some(OnnxTensor {
+        name,
+        shape,
+        values,
+    }))
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Bytes(&'a [u8]),
+    Fixed32([u8; 4]),
+}
+
+/// The `(field number, value)` pairs of an encoded protobuf message
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    /// The values of a packed repeated integer field
+    fn varints(mut bytes: &'a [u8]) -> impl Iterator<Item = Result<u64, OnnxError>> + 'a {
+        std::iter::from_fn(move || (!bytes.is_empty()).then(|| read_varint(&mut bytes)))
+    }
+
+    fn next_field(&mut self) -> Result<(u64, Value<'a>), OnnxError> {
+        let tag = read_varint(&mut self.0)?;
+        let value = match tag & 0b111 {
+            Message::VARINT => Value::Varint(read_varint(&mut self.0)?),
+            Message::FIXED64 => Value::Fixed64(self.take(8)?.try_into().unwrap()),
+            Message::LENGTH_DELIMITED => {
+                let length = read_varint(&mut self.0)? as usize;
+                Value::Bytes(self.take(length)?)
+            }
+            Message::FIXED32 => Value::Fixed32(self.take(4)?.try_into().unwrap()),
+            _ => return Err(OnnxError::Malformed("unsupported protobuf wire type")),
+        };
+
+        Ok((tag >> 3, value))
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], OnnxError> {
+        if length > self.0.len() {
+            return Err(OnnxError::Malformed("the file is truncated"));
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(taken)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>), OnnxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let field = self.next_field();
+        // Nothing after a malformed field can be trusted
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, OnnxError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or(OnnxError::Malformed("the file is truncated"))?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(OnnxError::Malformed("a varint is too long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ModelConfig, Network, quantize::tensor_values};
+    use super::*;
+    use burn::backend::NdArray;
+
+    /// A `ModelProto` whose graph holds a single 2x2 float initializer
+    fn model(tensor: impl FnOnce(Message) -> Message) -> Vec<u8> {
+        let tensor = tensor(
+            Message::default()
+                .int(1, 2)
+                .int(1, 2)
+                .int(2, FLOAT)
+                .string(8, "weight"),
+        );
+        let graph = Message::default().message(5, tensor);
+        Message::default().message(7, graph).0
+    }
+
+    fn external_data(bytes: &[u8]) -> Option<String> {
+        match read_initializers(bytes) {
+            Err(OnnxError::ExternalData(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_raw_data() {
+        let raw = [1.0f32, -2.0, 3.5, 0.0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        let tensors = read_initializers(&model(|tensor| tensor.bytes(9, &raw))).unwrap();
+
+        assert_eq!(tensors.len(), 1);
+        assert_eq!(tensors[0].name, "weight");
+        assert_eq!(tensors[0].shape, [2, 2]);
+        assert_eq!(tensors[0].values, [1.0, -2.0, 3.5, 0.0]);
+    }
+
+    #[test]
+    fn external_data_is_reported() {
+        let location = model(|tensor| tensor.int(14, 1));
+        assert_eq!(external_data(&location).as_deref(), Some("weight"));
+
+        let entry = Message::default()
+            .string(1, "location")
+            .string(2, "weights.bin");
+        let entries = model(|tensor| tensor.message(13, entry).int(14, 1));
+        assert_eq!(external_data(&entries).as_deref(), Some("weight"));
+
+        // `DEFAULT` keeps the data in the tensor, here with none, so it's just malformed
+        let default = model(|tensor| tensor.int(14, 0));
+        assert!(matches!(
+            read_initializers(&default),
+            Err(OnnxError::Malformed(_))
+        ));
+    }
+
+    /// The `op_type` of every node in the graph, in order
+    fn op_types(bytes: &[u8]) -> Vec<String> {
+        let mut op_types = Vec::new();
+        for field in Fields(bytes) {
+            let (7, Value::Bytes(graph)) = field.unwrap() else {
+                continue;
+            };
+            for field in Fields(graph) {
+                let (1, Value::Bytes(node)) = field.unwrap() else {
+                    continue;
+                };
+                for field in Fields(node) {
+                    if let (4, Value::Bytes(op_type)) = field.unwrap() {
+                        op_types.push(String::from_utf8(op_type.to_vec()).unwrap());
+                    }
+                }
+            }
+        }
+        op_types
+    }
+
+    fn stored<const D: usize>(tensor: Tensor<NdArray, D>) -> (Vec<usize>, Vec<f32>) {
+        (tensor.dims().to_vec(), tensor_values(tensor))
+    }
+
+    #[test]
+    fn exported_models_read_back() {
+        let model = ModelConfig::new(10, vec![32]).init::<NdArray>(&Default::default());
+        let bytes = Network::Cnn(model.clone()).to_onnx([28, 28], 10, &[]);
+        let tensors = read_initializers(&bytes).unwrap();
+        let read = |prefix: &str| {
+            tensors
+                .iter()
+                .filter(|tensor| tensor.name.starts_with(prefix))
+                .map(|tensor| (tensor.shape.clone(), tensor.values.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        let conv_weights = model
+            .conv_blocks
+            .iter()
+            .map(|block| stored(block.conv.weight.val()))
+            .collect::<Vec<_>>();
+        // Gemm takes burn's `[inputs, outputs]` layout as it is
+        let linear_weights = model
+            .linears
+            .iter()
+            .map(|linear| stored(linear.weight.val()))
+            .collect::<Vec<_>>();
+        let linear_biases = model
+            .linears
+            .iter()
+            .map(|linear| stored(linear.bias.as_ref().unwrap().val()))
+            .collect::<Vec<_>>();
+        assert_eq!(read("conv.weight"), conv_weights);
+        assert_eq!(read("linear.weight"), linear_weights);
+        assert_eq!(read("linear.bias"), linear_biases);
+
+        // Names are unique, so importers can tell the weights apart
+        let names = tensors
+            .iter()
+            .map(|tensor| &tensor.name)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(names.len(), tensors.len());
+
+        let layers = op_types(&bytes)
+            .into_iter()
+            .filter(|op_type| matches!(op_type.as_str(), "Conv" | "Relu" | "Gemm"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layers,
+            ["Conv", "Relu", "Conv", "Relu", "Gemm", "Relu", "Gemm"]
+        );
+    }
+
+    #[test]
+    fn input_major_weights_follow_trans_b() {
+        let node = |op_type: &str, weight: &str, trans_b: Option<i64>| {
+            let mut node = Message::default()
+                .string(1, "x")
+                .string(1, weight)
+                .string(4, op_type);
+            if let Some(trans_b) = trans_b {
+                node = node.message(5, Attribute::int("transB", trans_b).encode());
+            }
+            node
+        };
+        let graph = Message::default()
+            .message(1, node("Gemm", "exported", None))
+            .message(1, node("Gemm", "untransposed", Some(0)))
+            .message(1, node("Gemm", "pytorch", Some(1)))
+            .message(1, node("MatMul", "matmul", None))
+            .message(1, node("Conv", "conv", None));
+        let model = Message::default().message(7, graph).0;
+
+        assert_eq!(
+            read_input_major_weights(&model).unwrap(),
+            BTreeSet::from(["exported", "matmul", "untransposed"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn matrices_transpose() {
+        let tensor = OnnxTensor {
+            name: "weight".to_owned(),
+            shape: vec![2, 3],
+            values: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        }
+        .transposed();
+
+        assert_eq!(tensor.shape, [3, 2]);
+        assert_eq!(tensor.values, [1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+    }
+}
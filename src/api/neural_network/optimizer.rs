@@ -0,0 +1,71 @@
+use burn::optim::{AdamConfig, AdamWConfig, RmsPropConfig, SgdConfig};
+use serde::{Deserialize, Deserializer, Serialize, de::Error};
+
+/// The optimizer and its burn config, picked with `kind` in the `optimizer` config section.
+/// Every config takes `weight_decay` and gradient clipping, as `grad_clipping` or SGD's
+/// `gradient_clipping`. SGD's `momentum` has `nesterov`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum OptimizerConfig {
+    Sgd(SgdConfig),
+    Adam(AdamConfig),
+    #[serde(rename = "adamw")]
+    AdamW(AdamWConfig),
+    #[serde(rename = "rmsprop")]
+    RmsProp(RmsPropConfig),
+}
+
+impl OptimizerConfig {
+    /// Reads a section without `kind` as Adam, the only optimizer configs had before
+    pub(crate) fn deserialize_or_adam<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let mut section = serde_json::Value::deserialize(deserializer)?;
+        if let Some(section) = section.as_object_mut() {
+            section.entry("kind").or_insert_with(|| "adam".into());
+        }
+        serde_json::from_value(section).map_err(D::Error::custom)
+    }
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        OptimizerConfig::Adam(AdamConfig::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        #[serde(deserialize_with = "OptimizerConfig::deserialize_or_adam")]
+        optimizer: OptimizerConfig,
+    }
+
+    fn parse(toml: &str) -> Result<OptimizerConfig, toml::de::Error> {
+        toml::from_str::<Config>(toml).map(|config| config.optimizer)
+    }
+
+    #[test]
+    fn sections_without_kind_are_adam() {
+        let optimizer = parse("[optimizer]\nbeta_1 = 0.8\nbeta_2 = 0.999\nepsilon = 1e-6").unwrap();
+        let OptimizerConfig::Adam(adam) = optimizer else {
+            panic!("expected Adam, got {optimizer:?}");
+        };
+        let expected = AdamConfig::new()
+            .with_beta_1(0.8)
+            .with_beta_2(0.999)
+            .with_epsilon(1e-6);
+        assert_eq!(
+            serde_json::to_value(adam).unwrap(),
+            serde_json::to_value(expected).unwrap()
+        );
+
+        let optimizer = parse("[optimizer]\nkind = \"sgd\"").unwrap();
+        assert!(matches!(optimizer, OptimizerConfig::Sgd(_)));
+
+        assert!(parse("[optimizer]\nkind = \"lbfgs\"").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use burn::prelude::*;
+use image::{DynamicImage, GrayImage, imageops::FilterType};
+use std::path::Path;
+
+// MNIST's pixel statistics, every input is normalized with these
+const MEAN: f64 = 0.1307;
+const STD: f64 = 0.3081;
+
+/// Turns an image into the pixels the model is trained on.
+///
+/// Training, validation and inference all go through this and [`normalize`], so a given image
+/// always ends up as the same tensor.
+#[derive(Clone, Debug)]
+pub(crate) struct Preprocessor {
+    width: u32,
+    height: u32,
+    /// Flip dark-on-light images, the model expects light strokes on a dark background
+    invert: bool,
+    mode: PreprocessMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum PreprocessMode {
+    /// Resize the whole image, fine for inputs that already look like the training data
+    #[default]
+    Resize,
+    /// Crop the digit and center it the way MNIST was built, for photos and scans
+    Mnist,
+}
+
+impl Preprocessor {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            invert: false,
+            mode: PreprocessMode::default(),
+        }
+    }
+
+    pub(crate) fn with_mode(mut self, mode: PreprocessMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub(crate) fn with_invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    /// The `[height, width]` of the preprocessed images
+    pub(crate) fn size(&self) -> [usize; 2] {
+        [self.height as usize, self.width as usize]
+    }
+
+    /// What's needed to reproduce the preprocessing outside this crate, as key-value pairs
+    pub(crate) fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "input_layout",
+                "[batch, height, width], grayscale".to_owned(),
+            ),
+            ("input_height", self.height.to_string()),
+            ("input_width", self.width.to_string()),
+            ("normalization", "(pixel / 255 - mean) / std".to_owned()),
+            ("mean", MEAN.to_string()),
+            ("std", STD.to_string()),
+            // Light strokes on a dark background are expected
+            ("invert", self.invert.to_string()),
+        ]
+    }
+
+    pub(crate) fn open(&self, path: impl AsRef<Path>) -> image::ImageResult<Vec<f32>> {
+        Ok(self.pixels(&image::open(path)?))
+    }
+
+    /// Grayscale pixels in `0..=255`, resized and stored row-major
+    pub(crate) fn pixels(&self, image: &DynamicImage) -> Vec<f32> {
+        let mut grayscale = image.to_luma8();
+
+        let resized = match self.mode {
+            PreprocessMode::Resize => {
+                if self.invert {
+                    image::imageops::invert(&mut grayscale);
+                }
+                image::imageops::resize(&grayscale, self.width, self.height, FilterType::Lanczos3)
+            }
+            PreprocessMode::Mnist => {
+                if self.invert || has_light_background(&grayscale) {
+                    image::imageops::invert(&mut grayscale);
+                }
+                self.center_digit(grayscale)
+            }
+        };
+
+        resized.into_raw().into_iter().map(f32::from).collect()
+    }
+
+    /// MNIST's own preprocessing: the digit is cropped, fit into a 20x20 box keeping its aspect
+    /// ratio, then pasted onto the 28x28 canvas so its center of mass sits in the middle.
+    ///
+    /// Other sizes scale the box the same way, e.g. 40x40 for a 56x56 input.
+    fn center_digit(&self, mut image: GrayImage) -> GrayImage {
+        let mut canvas = GrayImage::new(self.width, self.height);
+
+        // Drop the background noise so it doesn't count towards the bounding box
+        let threshold = otsu_threshold(&image);
+        for pixel in image.pixels_mut() {
+            if pixel.0[0] <= threshold {
+                pixel.0[0] = 0;
+            }
+        }
+
+        let Some((x, y, width, height)) = bounding_box(&image) else {
+            return canvas;
+        };
+        let digit = image::imageops::crop_imm(&image, x, y, width, height).to_image();
+
+        let box_size = |size: u32| (size * 20 / 28).max(1) as f64;
+        let scale =
+            (box_size(self.width) / width as f64).min(box_size(self.height) / height as f64);
+        let fit_width = ((width as f64 * scale).round() as u32).max(1);
+        let fit_height = ((height as f64 * scale).round() as u32).max(1);
+        let digit = image::imageops::resize(&digit, fit_width, fit_height, FilterType::Lanczos3);
+
+        let (center_x, center_y) = center_of_mass(&digit);
+        let offset = |canvas_size: u32, fit_size: u32, center: f64| {
+            let offset = (canvas_size as f64 / 2.0 - center).round() as i64;
+            offset.clamp(0, (canvas_size - fit_size) as i64)
+        };
+        image::imageops::overlay(
+            &mut canvas,
+            &digit,
+            offset(self.width, fit_width, center_x),
+            offset(self.height, fit_height, center_y),
+        );
+
+        canvas
+    }
+}
+
+/// Guesses dark-on-light input from the border, which is background in any sensible photo
+fn has_light_background(image: &GrayImage) -> bool {
+    let (width, height) = image.dimensions();
+    let border = image
+        .enumerate_pixels()
+        .filter(|(x, y, _)| *x == 0 || *y == 0 || *x == width - 1 || *y == height - 1)
+        .map(|(_, _, pixel)| pixel.0[0] as u64)
+        .collect::<Vec<_>>();
+
+    border.iter().sum::<u64>() > 127 * border.len() as u64
+}
+
+/// Otsu's method: the threshold that best separates the histogram into two classes
+fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let total = image.pixels().len() as f64;
+    let sum_all = (0..256)
+        .map(|i| i as f64 * histogram[i] as f64)
+        .sum::<f64>();
+
+    let (mut weight_background, mut sum_background) = (0.0, 0.0);
+    let (mut best_threshold, mut best_variance) = (0, 0.0);
+
+    for (threshold, &count) in histogram.iter().enumerate() {
+        weight_background += count as f64;
+        let weight_foreground = total - weight_background;
+        if weight_background == 0.0 || weight_foreground == 0.0 {
+            continue;
+        }
+
+        sum_background += threshold as f64 * count as f64;
+        let mean_background = sum_background / weight_background;
+        let mean_foreground = (sum_all - sum_background) / weight_foreground;
+
+        let variance =
+            weight_background * weight_foreground * (mean_background - mean_foreground).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_threshold = threshold as u8;
+        }
+    }
+
+    best_threshold
+}
+
+/// `(x, y, width, height)` of the non-zero pixels
+fn bounding_box(image: &GrayImage) -> Option<(u32, u32, u32, u32)> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.0[0] > 0 {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    (min_x <= max_x).then(|| (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+/// Intensity weighted `(x, y)` center, the middle of the image when it's blank
+fn center_of_mass(image: &GrayImage) -> (f64, f64) {
+    let (mut mass, mut x_mass, mut y_mass) = (0.0, 0.0, 0.0);
+
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let value = pixel.0[0] as f64;
+        mass += value;
+        // Pixel centers sit half a pixel in
+        x_mass += value * (x as f64 + 0.5);
+        y_mass += value * (y as f64 + 0.5);
+    }
+
+    if mass == 0.0 {
+        let (width, height) = image.dimensions();
+        return (width as f64 / 2.0, height as f64 / 2.0);
+    }
+
+    (x_mass / mass, y_mass / mass)
+}
+
+/// Builds a `[batch_size, height, width]` tensor from `0..=255` pixels, normalized with MNIST's
+/// mean and standard deviation
+pub(crate) fn normalize<B: Backend>(
+    pixels: Vec<f32>,
+    shape: [usize; 3],
+    device: &B::Device,
+) -> Tensor<B, 3> {
+    let data = TensorData::new(pixels, shape).convert::<B::FloatElem>();
+    let tensor = Tensor::<B, 3>::from_data(data, device);
+
+    ((tensor / 255) - MEAN) / STD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ImageFolderDataset, ImageItem, MnistBatch, MnistBatcher};
+    use super::*;
+    use burn::{
+        backend::NdArray,
+        data::{
+            dataloader::batcher::Batcher,
+            dataset::{Dataset, vision::MnistItem},
+        },
+    };
+    use image::{Rgb, RgbImage};
+
+    type B = NdArray;
+
+    /// A dark digit-ish blob on a light, slightly noisy colored background
+    fn photo(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (dx, dy) = (x as i64 - width as i64 / 2, y as i64 - height as i64 / 3);
+            if dx * dx + dy * dy < (width as i64 / 5).pow(2) {
+                Rgb([20, 30, 25])
+            } else {
+                Rgb([230 + (x % 7) as u8, 220 + (y % 5) as u8, 210])
+            }
+        }))
+    }
+
+    fn predict_tensor(preprocessor: &Preprocessor, image: &DynamicImage) -> Tensor<B, 3> {
+        let [height, width] = preprocessor.size();
+        normalize::<B>(
+            preprocessor.pixels(image),
+            [1, height, width],
+            &Default::default(),
+        )
+    }
+
+    fn train_tensor(item: ImageItem) -> Tensor<B, 3> {
+        let batch: MnistBatch<B> = MnistBatcher::default().batch(vec![item], &Default::default());
+        batch.images
+    }
+
+    fn assert_identical(train: Tensor<B, 3>, predict: Tensor<B, 3>) {
+        assert_eq!(train.dims(), predict.dims());
+        let train = train.into_data().to_vec::<f32>().unwrap();
+        let predict = predict.into_data().to_vec::<f32>().unwrap();
+        for (index, (train, predict)) in train.iter().zip(&predict).enumerate() {
+            assert_eq!(train.to_bits(), predict.to_bits(), "pixel {index} differs");
+        }
+    }
+
+    #[test]
+    fn mnist_items_match_predicted_images() {
+        let mut image = [[0.0; 28]; 28];
+        for (y, row) in image.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = ((x * 11 + y * 7) % 256) as f32;
+            }
+        }
+        let gray = GrayImage::from_fn(28, 28, |x, y| {
+            image::Luma([image[y as usize][x as usize] as u8])
+        });
+
+        let item = ImageItem::from(MnistItem { image, label: 3 });
+        let predict = predict_tensor(&Preprocessor::new(28, 28), &DynamicImage::ImageLuma8(gray));
+
+        assert_identical(train_tensor(item), predict);
+    }
+
+    #[test]
+    fn image_folders_match_predicted_images() {
+        let root = std::env::temp_dir().join(format!("preprocess-test-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("7")).unwrap();
+        let path = root.join("7").join("digit.png");
+        photo(50, 40).save(&path).unwrap();
+        let image = image::open(&path).unwrap();
+
+        let preprocessors = [
+            Preprocessor::new(28, 28).with_invert(true),
+            Preprocessor::new(32, 24),
+            Preprocessor::new(28, 28).with_mode(PreprocessMode::Mnist),
+        ];
+        let items = preprocessors
+            .iter()
+            .map(|preprocessor| {
+                ImageFolderDataset::new(&root, preprocessor).map(|dataset| dataset.get(0))
+            })
+            .collect::<Vec<_>>();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        for (preprocessor, item) in preprocessors.iter().zip(items) {
+            let item = item.unwrap().unwrap();
+            assert_identical(train_tensor(item), predict_tensor(preprocessor, &image));
+        }
+    }
+}
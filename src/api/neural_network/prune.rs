@@ -0,0 +1,296 @@
+use super::{Model, Network, quantize::tensor_values};
+use burn::{
+    module::Param,
+    optim::{GradientsParams, LearningRate, Optimizer},
+    prelude::*,
+    tensor::backend::AutodiffBackend,
+};
+use serde::Serialize;
+
+/// How many of a layer's weights are zero
+#[derive(Debug, Serialize)]
+pub(crate) struct LayerSparsity {
+    pub(crate) layer: String,
+    pub(crate) shape: Vec<usize>,
+    pub(crate) zeros: usize,
+    pub(crate) sparsity: f64,
+}
+
+/// The weights `Model::prune` kept, 1 where a weight is kept and 0 where it was zeroed
+#[derive(Clone, Debug)]
+pub(crate) struct PruneMask<B: Backend> {
+    conv_blocks: Vec<Tensor<B, 4>>,
+    linears: Vec<Tensor<B, 2>>,
+}
+
+impl<B: AutodiffBackend> PruneMask<B> {
+    /// The mask of a model pruned without autodiff, for fine-tuning it
+    pub(crate) fn from_inner(mask: PruneMask<B::InnerBackend>) -> Self {
+        Self {
+            conv_blocks: mask
+                .conv_blocks
+                .into_iter()
+                .map(Tensor::from_inner)
+                .collect(),
+            linears: mask.linears.into_iter().map(Tensor::from_inner).collect(),
+        }
+    }
+}
+
+impl<B: Backend> Model<B> {
+    /// Zeros the `sparsity` fraction of smallest magnitude weights in every conv kernel and
+    /// linear layer. Biases and batch norm are left as they are.
+    ///
+    /// Weights that are already zero go first, so pruning again to a higher sparsity keeps the
+    /// earlier mask
+    pub(crate) fn prune(self, sparsity: f64) -> (Self, PruneMask<B>) {
+        let mask = PruneMask {
+            conv_blocks: self
+                .conv_blocks
+                .iter()
+                .map(|block| keep_mask(block.conv.weight.val(), sparsity))
+                .collect(),
+            linears: self
+                .linears
+                .iter()
+                .map(|linear| keep_mask(linear.weight.val(), sparsity))
+                .collect(),
+        };
+
+        (self.masked(&mask), mask)
+    }
+
+    /// Zeros the weights `mask` pruned again, keeping the parameters' ids so the optimizer
+    /// state carries on
+    pub(crate) fn masked(mut self, mask: &PruneMask<B>) -> Self {
+        for (block, mask) in self.conv_blocks.iter_mut().zip(&mask.conv_blocks) {
+            block.conv.weight = apply_mask(block.conv.weight.clone(), mask);
+        }
+        for (linear, mask) in self.linears.iter_mut().zip(&mask.linears) {
+            linear.weight = apply_mask(linear.weight.clone(), mask);
+        }
+        self
+    }
+
+    /// The weights `prune` zeros, layer by layer
+    pub(crate) fn sparsity(&self) -> Vec<LayerSparsity> {
+        let conv_blocks = self.conv_blocks.iter().enumerate().map(|(index, block)| {
+            layer_sparsity(
+                format!("conv_blocks.{index}.conv.weight"),
+                block.conv.weight.val(),
+            )
+        });
+        let linears = self.linears.iter().enumerate().map(|(index, linear)| {
+            layer_sparsity(format!("linears.{index}.weight"), linear.weight.val())
+        });
+        conv_blocks.chain(linears).collect()
+    }
+}
+
+/// Runs `optimizer`, then zeros the pruned weights again so fine-tuning can't regrow them
+#[derive(Clone)]
+pub(crate) struct MaskedOptimizer<O, B: Backend> {
+    optimizer: O,
+    mask: PruneMask<B>,
+}
+
+impl<O, B: Backend> MaskedOptimizer<O, B> {
+    pub(crate) fn new(optimizer: O, mask: PruneMask<B>) -> Self {
+        Self { optimizer, mask }
+    }
+}
+
+impl<O, B> Optimizer<Network<B>, B> for MaskedOptimizer<O, B>
+where
+    B: AutodiffBackend,
+    O: Optimizer<Network<B>, B>,
+{
+    type Record = O::Record;
+
+    fn step(&mut self, lr: LearningRate, module: Network<B>, grads: GradientsParams) -> Network<B> {
+        match self.optimizer.step(lr, module, grads) {
+            Network::Cnn(model) => Network::Cnn(model.masked(&self.mask)),
+            network => network,
+        }
+    }
+
+    fn to_record(&self) -> Self::Record {
+        self.optimizer.to_record()
+    }
+
+    fn load_record(self, record: Self::Record) -> Self {
+        Self {
+            optimizer: self.optimizer.load_record(record),
+            mask: self.mask,
+        }
+    }
+}
+
+/// 0 for the `sparsity` fraction of smallest magnitude values, exactly that many even when
+/// magnitudes tie, and 1 for the rest
+fn keep_mask<B: Backend, const D: usize>(weight: Tensor<B, D>, sparsity: f64) -> Tensor<B, D> {
+    let device = weight.device();
+    let shape = weight.dims();
+    let values = tensor_values(weight);
+    let mut keep = vec![1.0f32; values.len()];
+
+    let count = (values.len() as f64 * sparsity.clamp(0.0, 1.0)).round() as usize;
+    if count > 0 {
+        let mut order = (0..values.len()).collect::<Vec<_>>();
+        order.select_nth_unstable_by(count - 1, |a, b| {
+            values[*a].abs().total_cmp(&values[*b].abs())
+        });
+        for index in &order[..count] {
+            keep[*index] = 0.0;
+        }
+    }
+
+    Tensor::from_data(TensorData::new(keep, shape), &device)
+}
+
+fn apply_mask<B: Backend, const D: usize>(
+    weight: Param<Tensor<B, D>>,
+    mask: &Tensor<B, D>,
+) -> Param<Tensor<B, D>> {
+    weight.map(|weight| {
+        let require_grad = weight.is_require_grad();
+        // Detached so the weight stays a leaf the next backward pass computes gradients for
+        (weight * mask.clone())
+            .detach()
+            .set_require_grad(require_grad)
+    })
+}
+
+fn layer_sparsity<B: Backend, const D: usize>(
+    layer: String,
+    weight: Tensor<B, D>,
+) -> LayerSparsity {
+    let shape = weight.dims().to_vec();
+    let values = tensor_values(weight);
+    let zeros = values.iter().filter(|value| **value == 0.0).count();
+
+    LayerSparsity {
+        layer,
+        shape,
+        zeros,
+        sparsity: zeros as f64 / values.len().max(1) as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Classifier, ModelConfig};
+    use super::*;
+    use burn::{
+        backend::{Autodiff, NdArray},
+        optim::AdamConfig,
+        tensor::Distribution,
+    };
+
+    type B = NdArray;
+
+    fn kept(values: &[f32], sparsity: f64) -> Vec<f32> {
+        let weight = Tensor::<B, 1>::from_floats(values, &Default::default());
+        tensor_values(keep_mask(weight, sparsity))
+    }
+
+    fn zeros(model: &Model<impl Backend>) -> Vec<Vec<bool>> {
+        let weights = model
+            .conv_blocks
+            .iter()
+            .map(|block| tensor_values(block.conv.weight.val()))
+            .chain(
+                model
+                    .linears
+                    .iter()
+                    .map(|linear| tensor_values(linear.weight.val())),
+            );
+        weights
+            .map(|values| values.iter().map(|value| *value == 0.0).collect())
+            .collect()
+    }
+
+    #[test]
+    fn smallest_magnitudes_are_pruned() {
+        let values = [0.5, -0.1, 2.0, -3.0, 0.05, 0.7, -0.3, 1.5, 0.2, -0.9];
+        assert_eq!(
+            kept(&values, 0.5),
+            [0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn ties_prune_the_exact_count() {
+        let values = [1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        for (sparsity, count) in [(0.3, 3), (0.5, 5), (0.94, 9)] {
+            let zeros = kept(&values, sparsity)
+                .iter()
+                .filter(|keep| **keep == 0.0)
+                .count();
+            assert_eq!(zeros, count, "sparsity {sparsity}");
+        }
+    }
+
+    #[test]
+    fn zero_sparsity_keeps_everything() {
+        let model = ModelConfig::new(10, vec![32]).init::<B>(&Default::default());
+        let before = tensor_values(model.linears[0].weight.val());
+        let (model, _) = model.prune(0.0);
+
+        assert_eq!(tensor_values(model.linears[0].weight.val()), before);
+        assert!(model.sparsity().iter().all(|layer| layer.zeros == 0));
+    }
+
+    #[test]
+    fn pruning_further_keeps_the_earlier_mask() {
+        let model = ModelConfig::new(10, vec![32]).init::<B>(&Default::default());
+        let (model, _) = model.prune(0.3);
+        let first = zeros(&model);
+        let (model, _) = model.prune(0.6);
+
+        for (first, second) in first.iter().zip(zeros(&model)) {
+            assert!(
+                first
+                    .iter()
+                    .zip(&second)
+                    .all(|(first, second)| !first || *second)
+            );
+        }
+        for layer in model.sparsity() {
+            let size = layer.shape.iter().product::<usize>();
+            assert_eq!(
+                layer.zeros,
+                (size as f64 * 0.6).round() as usize,
+                "{}",
+                layer.layer
+            );
+        }
+    }
+
+    #[test]
+    fn fine_tuning_keeps_pruned_weights_at_zero() {
+        type A = Autodiff<B>;
+        let device = Default::default();
+        let (model, mask) = ModelConfig::new(10, vec![32]).init::<A>(&device).prune(0.7);
+        let pruned = zeros(&model);
+        let before = tensor_values(model.linears[0].weight.val());
+
+        let mut optimizer = MaskedOptimizer::new(AdamConfig::new().init(), mask);
+        let mut network = Network::Cnn(model);
+        let images = Tensor::<A, 3>::random([4, 28, 28], Distribution::Default, &device);
+        let targets = Tensor::<A, 1, Int>::from_ints([1, 3, 5, 7], &device);
+        for _ in 0..3 {
+            let loss = network
+                .forward_classification(images.clone(), targets.clone())
+                .loss;
+            let grads = GradientsParams::from_grads(loss.backward(), &network);
+            network = optimizer.step(1.0e-2, network, grads);
+        }
+
+        let Network::Cnn(model) = network else {
+            unreachable!()
+        };
+        assert_eq!(zeros(&model), pruned);
+        assert_ne!(tensor_values(model.linears[0].weight.val()), before);
+    }
+}
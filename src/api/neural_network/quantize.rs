@@ -0,0 +1,490 @@
+use super::{
+    Model,
+    safetensors::{StoredTensor, StoredValues, decode_safetensors, encode_safetensors},
+};
+use burn::{
+    module::{Param, RunningState},
+    prelude::*,
+    record::RecorderError,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+/// How finely the int8 weights are scaled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Granularity {
+    /// One scale for each weight tensor
+    #[default]
+    PerTensor,
+    /// One scale for each output channel
+    PerChannel,
+}
+
+/// Written into the model config by `quantize`, the model is then read from int8 weights and
+/// dequantized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct QuantizationConfig {
+    pub(crate) granularity: Granularity,
+    /// The calibrated input of every conv and linear layer, in the order they run
+    pub(crate) activation_ranges: Vec<ActivationRange>,
+}
+
+/// Values seen going into a layer during calibration, always including 0
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ActivationRange {
+    min: f32,
+    max: f32,
+}
+
+impl ActivationRange {
+    fn of<B: Backend, const D: usize>(x: &Tensor<B, D>) -> Self {
+        Self {
+            min: x.clone().min().into_scalar().elem::<f32>().min(0.0),
+            max: x.clone().max().into_scalar().elem::<f32>().max(0.0),
+        }
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Rounds `x` to the 256 uint8 levels of the range, values outside of it are clamped
+    fn fake_quantize<B: Backend, const D: usize>(self, x: Tensor<B, D>) -> Tensor<B, D> {
+        let scale = (self.max - self.min) / 255.0;
+        if scale <= 0.0 {
+            return x;
+        }
+
+        let zero_point = (-self.min / scale).round();
+        x.div_scalar(scale)
+            .round()
+            .add_scalar(zero_point)
+            .clamp(0.0, 255.0)
+            .sub_scalar(zero_point)
+            .mul_scalar(scale)
+    }
+}
+
+impl<B: Backend> Model<B> {
+    /// Runs `images` through the float model, widening `ranges` to the input of every layer
+    pub(crate) fn calibrate(&self, images: Tensor<B, 3>, ranges: &mut Vec<ActivationRange>) {
+        self.forward_with(images, Some(ranges));
+    }
+
+    /// Records a layer's input while calibrating, rounds it to its calibrated range once the
+    /// model is quantized
+    pub(super) fn layer_input<const D: usize>(
+        &self,
+        layer: usize,
+        x: Tensor<B, D>,
+        calibration: Option<&mut Vec<ActivationRange>>,
+    ) -> Tensor<B, D> {
+        if let Some(ranges) = calibration {
+            let range = ActivationRange::of(&x);
+            match ranges.get_mut(layer) {
+                Some(seen) => seen.extend(range),
+                None => ranges.push(range),
+            }
+            return x;
+        }
+
+        match self.activation_ranges.get(layer) {
+            Some(range) => range.fake_quantize(x),
+            None => x,
+        }
+    }
+
+    /// The conv and linear weights as int8, the biases and batch norm stay f32
+    pub(crate) fn to_int8(&self, granularity: Granularity) -> Int8Weights {
+        let mut weights = Int8Weights::default();
+
+        for (index, block) in self.conv_blocks.iter().enumerate() {
+            let path = format!("conv_blocks.{index}");
+            // `[out_channels, in_channels, height, width]`
+            weights.insert_int8(
+                format!("{path}.conv.weight"),
+                block.conv.weight.val(),
+                0,
+                granularity,
+            );
+            if let Some(bias) = &block.conv.bias {
+                weights.insert_f32(format!("{path}.conv.bias"), bias.val());
+            }
+            if let Some(norm) = &block.norm {
+                weights.insert_f32(format!("{path}.norm.gamma"), norm.gamma.val());
+                weights.insert_f32(format!("{path}.norm.beta"), norm.beta.val());
+                weights.insert_f32(
+                    format!("{path}.norm.running_mean"),
+                    norm.running_mean.value(),
+                );
+                weights.insert_f32(format!("{path}.norm.running_var"), norm.running_var.value());
+            }
+        }
+
+        for (index, linear) in self.linears.iter().enumerate() {
+            // `[inputs, outputs]`
+            weights.insert_int8(
+                format!("linears.{index}.weight"),
+                linear.weight.val(),
+                1,
+                granularity,
+            );
+            if let Some(bias) = &linear.bias {
+                weights.insert_f32(format!("linears.{index}.bias"), bias.val());
+            }
+        }
+
+        weights
+    }
+
+    /// Replaces the weights with the dequantized `weights` and rounds the layer inputs from
+    /// then on. This simulates int8 inference, the model still computes in f32
+    pub(crate) fn load_int8(
+        mut self,
+        weights: &Int8Weights,
+        quantization: &QuantizationConfig,
+        device: &B::Device,
+    ) -> Result<Self, RecorderError> {
+        let layers = self.conv_blocks.len() + self.linears.len();
+        if quantization.activation_ranges.len() != layers {
+            return Err(RecorderError::Unknown(format!(
+                "The quantization config has {} activation ranges for {layers} layers",
+                quantization.activation_ranges.len()
+            )));
+        }
+
+        for (index, block) in self.conv_blocks.iter_mut().enumerate() {
+            let path = format!("conv_blocks.{index}");
+            let conv = &mut block.conv;
+            conv.weight = Param::from_tensor(weights.tensor(
+                &format!("{path}.conv.weight"),
+                conv.weight.dims(),
+                device,
+            )?);
+            if let Some(bias) = &mut conv.bias {
+                *bias = Param::from_tensor(weights.tensor(
+                    &format!("{path}.conv.bias"),
+                    bias.dims(),
+                    device,
+                )?);
+            }
+            if let Some(norm) = &mut block.norm {
+                let dims = norm.gamma.dims();
+                norm.gamma = Param::from_tensor(weights.tensor(
+                    &format!("{path}.norm.gamma"),
+                    dims,
+                    device,
+                )?);
+                norm.beta = Param::from_tensor(weights.tensor(
+                    &format!("{path}.norm.beta"),
+                    dims,
+                    device,
+                )?);
+                norm.running_mean = RunningState::new(weights.tensor(
+                    &format!("{path}.norm.running_mean"),
+                    dims,
+                    device,
+                )?);
+                norm.running_var = RunningState::new(weights.tensor(
+                    &format!("{path}.norm.running_var"),
+                    dims,
+                    device,
+                )?);
+            }
+        }
+
+        for (index, linear) in self.linears.iter_mut().enumerate() {
+            linear.weight = Param::from_tensor(weights.tensor(
+                &format!("linears.{index}.weight"),
+                linear.weight.dims(),
+                device,
+            )?);
+            if let Some(bias) = &mut linear.bias {
+                *bias = Param::from_tensor(weights.tensor(
+                    &format!("linears.{index}.bias"),
+                    bias.dims(),
+                    device,
+                )?);
+            }
+        }
+
+        self.activation_ranges.0 = quantization.activation_ranges.clone();
+        Ok(self)
+    }
+}
+
+/// Weights as `quantize` saves them, in a safetensors file. An int8 tensor `name` is scaled by
+/// the f32 tensor `name.scale`, whose shape broadcasts over it
+#[derive(Default)]
+pub(crate) struct Int8Weights {
+    tensors: BTreeMap<String, StoredTensor>,
+}
+
+impl Int8Weights {
+    /// Added to the model path, next to where the float weights would be
+    pub(crate) const EXTENSION: &str = "int8.safetensors";
+
+    fn insert_f32<B: Backend, const D: usize>(&mut self, name: String, tensor: Tensor<B, D>) {
+        let shape = tensor.dims().to_vec();
+        let values = tensor_values(tensor);
+        self.tensors.insert(
+            name,
+            StoredTensor {
+                shape,
+                values: StoredValues::F32(values),
+            },
+        );
+    }
+
+    /// Symmetric, so zeros stay exactly zero. Per channel, every index along `channel_dim`
+    /// gets its own scale
+    fn insert_int8<B: Backend, const D: usize>(
+        &mut self,
+        name: String,
+        tensor: Tensor<B, D>,
+        channel_dim: usize,
+        granularity: Granularity,
+    ) {
+        let shape = tensor.dims().to_vec();
+        let values = tensor_values(tensor);
+        let scale_shape = shape
+            .iter()
+            .enumerate()
+            .map(|(dim, size)| match granularity {
+                Granularity::PerChannel if dim == channel_dim => *size,
+                _ => 1,
+            })
+            .collect::<Vec<_>>();
+
+        let mut max = vec![0.0f32; scale_shape.iter().product()];
+        for (index, value) in values.iter().enumerate() {
+            let scale = scale_index(index, &shape, &scale_shape);
+            max[scale] = max[scale].max(value.abs());
+        }
+        let scales = max
+            .into_iter()
+            .map(|max| if max > 0.0 { max / 127.0 } else { 1.0 })
+            .collect::<Vec<_>>();
+
+        let quantized = values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let scale = scales[scale_index(index, &shape, &scale_shape)];
+                (value / scale).round().clamp(-127.0, 127.0) as i8
+            })
+            .collect();
+
+        self.tensors.insert(
+            format!("{name}.scale"),
+            StoredTensor {
+                shape: scale_shape,
+                values: StoredValues::F32(scales),
+            },
+        );
+        self.tensors.insert(
+            name,
+            StoredTensor {
+                shape,
+                values: StoredValues::I8(quantized),
+            },
+        );
+    }
+
+    /// The f32 tensor `name`, dequantized when it's stored as int8
+    fn tensor<B: Backend, const D: usize>(
+        &self,
+        name: &str,
+        dims: [usize; D],
+        device: &B::Device,
+    ) -> Result<Tensor<B, D>, RecorderError> {
+        let stored = self.tensors.get(name).ok_or_else(|| {
+            RecorderError::Unknown(format!("{name} is missing from the int8 weights"))
+        })?;
+        if stored.shape != dims {
+            return Err(RecorderError::Unknown(format!(
+                "{name} is {:?} in the int8 weights, the model expects {dims:?}",
+                stored.shape
+            )));
+        }
+
+        let values = match &stored.values {
+            StoredValues::F32(values) => values.clone(),
+            StoredValues::I8(values) => {
+                let scale = self
+                    .tensors
+                    .get(&format!("{name}.scale"))
+                    .filter(|scale| {
+                        scale.shape.len() == dims.len()
+                            && scale
+                                .shape
+                                .iter()
+                                .zip(dims)
+                                .all(|(scale, size)| *scale == 1 || *scale == size)
+                    })
+                    .ok_or_else(|| {
+                        RecorderError::Unknown(format!("{name} has no matching scale"))
+                    })?;
+                let StoredValues::F32(scales) = &scale.values else {
+                    return Err(RecorderError::Unknown(format!(
+                        "{name}.scale is not stored as f32"
+                    )));
+                };
+
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        *value as f32 * scales[scale_index(index, &dims, &scale.shape)]
+                    })
+                    .collect()
+            }
+        };
+
+        Ok(Tensor::from_data(TensorData::new(values, dims), device))
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, encode_safetensors(&self.tensors)?)
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self, RecorderError> {
+        let bytes = std::fs::read(path)
+            .map_err(|error| RecorderError::FileNotFound(format!("{}: {error}", path.display())))?;
+        let tensors = decode_safetensors(&bytes).map_err(|error| {
+            RecorderError::DeserializeError(format!("{}: {error}", path.display()))
+        })?;
+        Ok(Self { tensors })
+    }
+}
+
+pub(super) fn tensor_values<B: Backend, const D: usize>(tensor: Tensor<B, D>) -> Vec<f32> {
+    tensor
+        .into_data()
+        .convert::<f32>()
+        .to_vec()
+        .expect("Converted data should be f32")
+}
+
+/// Index into a scale of `scale_shape` broadcast over `shape`, for element `index` of `shape`
+fn scale_index(index: usize, shape: &[usize], scale_shape: &[usize]) -> usize {
+    let mut remaining = index;
+    let mut position = 0;
+    let mut scale_stride = 1;
+
+    // Row major, so the last dim is the innermost one
+    for (size, scale_size) in shape.iter().zip(scale_shape).rev() {
+        let coordinate = remaining % size;
+        remaining /= size;
+        if *scale_size > 1 {
+            position += coordinate * scale_stride;
+        }
+        scale_stride *= scale_size;
+    }
+
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::NdArray;
+
+    type B = NdArray;
+
+    fn weight() -> Tensor<B, 2> {
+        // Channel 1 along dim 0 is 1000 times smaller than channel 0
+        Tensor::from_floats(
+            [[1.0, -0.5, 0.25], [0.001, -0.0005, 0.00025]],
+            &Default::default(),
+        )
+    }
+
+    fn reloaded(weights: &Int8Weights, name: &str) -> Vec<f32> {
+        let path = std::env::temp_dir().join(format!("int8-test-{}-{name}", std::process::id()));
+        weights.save(&path).unwrap();
+        let loaded = Int8Weights::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let tensor = loaded
+            .unwrap()
+            .tensor::<B, 2>(name, [2, 3], &Default::default())
+            .unwrap();
+        tensor_values(tensor)
+    }
+
+    #[test]
+    fn scales_broadcast_over_their_tensor() {
+        let indices = |shape: &[usize], scale_shape: &[usize]| {
+            (0..shape.iter().product())
+                .map(|index| scale_index(index, shape, scale_shape))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(indices(&[2, 3], &[1, 1]), [0, 0, 0, 0, 0, 0]);
+        assert_eq!(indices(&[2, 3], &[2, 1]), [0, 0, 0, 1, 1, 1]);
+        assert_eq!(indices(&[2, 3], &[1, 3]), [0, 1, 2, 0, 1, 2]);
+        assert_eq!(
+            indices(&[3, 2, 2, 2], &[3, 1, 1, 1]),
+            (0..24).map(|index| index / 8).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn int8_weights_survive_saving() {
+        let original = tensor_values(weight());
+
+        for (granularity, channel_dim) in [
+            (Granularity::PerTensor, 0),
+            (Granularity::PerChannel, 0),
+            (Granularity::PerChannel, 1),
+        ] {
+            let mut weights = Int8Weights::default();
+            weights.insert_int8("weight".to_owned(), weight(), channel_dim, granularity);
+            weights.insert_f32("bias".to_owned(), weight());
+
+            let expected_scale = match (granularity, channel_dim) {
+                (Granularity::PerTensor, _) => vec![1, 1],
+                (_, 0) => vec![2, 1],
+                _ => vec![1, 3],
+            };
+            assert_eq!(weights.tensors["weight.scale"].shape, expected_scale);
+
+            let before = tensor_values(
+                weights
+                    .tensor::<B, 2>("weight", [2, 3], &Default::default())
+                    .unwrap(),
+            );
+            let after = reloaded(&weights, "weight");
+            assert_eq!(before, after, "{granularity:?} along {channel_dim}");
+            assert_eq!(reloaded(&weights, "bias"), original);
+
+            // Every value is within half a step of its own scale
+            let scale = match &weights.tensors["weight.scale"].values {
+                StoredValues::F32(scales) => scales.clone(),
+                StoredValues::I8(_) => unreachable!(),
+            };
+            for (index, (value, original)) in after.iter().zip(&original).enumerate() {
+                let step = scale[scale_index(index, &[2, 3], &expected_scale)];
+                assert!(
+                    (value - original).abs() <= step / 2.0,
+                    "{value} vs {original}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn per_channel_scales_keep_small_channels() {
+        let mut weights = Int8Weights::default();
+        weights.insert_int8("weight".to_owned(), weight(), 0, Granularity::PerTensor);
+        weights.insert_int8("channel".to_owned(), weight(), 0, Granularity::PerChannel);
+
+        // One shared scale rounds the small channel away, its own scale keeps it exact
+        assert_eq!(&reloaded(&weights, "weight")[3..], [0.0, 0.0, 0.0]);
+        let channel = reloaded(&weights, "channel");
+        assert!((channel[3] - 0.001).abs() < 1e-7);
+    }
+}
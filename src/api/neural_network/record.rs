@@ -0,0 +1,76 @@
+use burn::{
+    prelude::*,
+    record::{
+        BinFileRecorder, CompactRecorder, FullPrecisionSettings, NamedMpkFileRecorder,
+        PrettyJsonFileRecorder, Record, Recorder, RecorderError,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// How model weights are written to disk. Kept in the training config, so loading uses the
+/// format the weights were saved with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RecordFormat {
+    /// Half precision named MessagePack, the smallest files
+    #[default]
+    Compact,
+    /// Full precision named MessagePack
+    NamedMpk,
+    /// Full precision bincode
+    Binary,
+    /// Full precision indented JSON, for diffing weights
+    PrettyJson,
+}
+
+impl RecordFormat {
+    /// What the recorder appends to the path it's given
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Compact | RecordFormat::NamedMpk => "mpk",
+            RecordFormat::Binary => "bin",
+            RecordFormat::PrettyJson => "json",
+        }
+    }
+
+    pub(crate) fn save<B: Backend, M: Module<B>>(
+        self,
+        module: M,
+        path: impl Into<PathBuf>,
+    ) -> Result<(), RecorderError> {
+        match self {
+            RecordFormat::Compact => module.save_file(path, &CompactRecorder::new()),
+            RecordFormat::NamedMpk => {
+                module.save_file(path, &NamedMpkFileRecorder::<FullPrecisionSettings>::new())
+            }
+            RecordFormat::Binary => {
+                module.save_file(path, &BinFileRecorder::<FullPrecisionSettings>::new())
+            }
+            RecordFormat::PrettyJson => module.save_file(
+                path,
+                &PrettyJsonFileRecorder::<FullPrecisionSettings>::new(),
+            ),
+        }
+    }
+
+    pub(crate) fn load<B: Backend, R: Record<B>>(
+        self,
+        path: impl Into<PathBuf>,
+        device: &B::Device,
+    ) -> Result<R, RecorderError> {
+        let path = path.into();
+        match self {
+            RecordFormat::Compact => CompactRecorder::new().load(path, device),
+            RecordFormat::NamedMpk => {
+                NamedMpkFileRecorder::<FullPrecisionSettings>::new().load(path, device)
+            }
+            RecordFormat::Binary => {
+                BinFileRecorder::<FullPrecisionSettings>::new().load(path, device)
+            }
+            RecordFormat::PrettyJson => {
+                PrettyJsonFileRecorder::<FullPrecisionSettings>::new().load(path, device)
+            }
+        }
+    }
+}
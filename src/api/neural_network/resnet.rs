@@ -0,0 +1,198 @@
+use super::{
+    Classifier, RecordFormat,
+    onnx::{FeatureMap, OnnxGraph},
+};
+use burn::{
+    config::Config,
+    nn::{
+        BatchNorm, BatchNormConfig, Linear, LinearConfig, PaddingConfig2d, Relu,
+        conv::{Conv2d, Conv2dConfig},
+        pool::{AdaptiveAvgPool2d, AdaptiveAvgPool2dConfig},
+    },
+    prelude::*,
+    record::RecorderError,
+};
+use std::path::PathBuf;
+
+#[derive(Debug, Config)]
+pub(crate) struct ResNetConfig {
+    /// Channels of each stage, every stage after the first halves the image size
+    #[config(default = "vec![16, 32, 64]")]
+    stages: Vec<usize>,
+    /// Residual blocks per stage
+    #[config(default = 2)]
+    blocks_per_stage: usize,
+}
+
+impl ResNetConfig {
+    pub(crate) fn init<B: Backend>(&self, num_classes: usize, device: &B::Device) -> ResNet<B> {
+        let stem_channels = self.stages.first().copied().unwrap_or(16);
+        let mut channels = stem_channels;
+        let mut blocks = Vec::new();
+
+        for (stage, &stage_channels) in self.stages.iter().enumerate() {
+            for block in 0..self.blocks_per_stage {
+                // Only the first block of a stage changes the size
+                let stride = if stage > 0 && block == 0 { 2 } else { 1 };
+                blocks.push(ResidualBlock::new(channels, stage_channels, stride, device));
+                channels = stage_channels;
+            }
+        }
+
+        ResNet {
+            stem: conv3x3(1, stem_channels, 1, device), // 1 input channel
+            stem_norm: BatchNormConfig::new(stem_channels).init(device),
+            blocks,
+            pool: AdaptiveAvgPool2dConfig::new([1, 1]).init(),
+            linear: LinearConfig::new(channels, num_classes).init(device),
+            activation: Relu::new(),
+        }
+    }
+
+    /// Builds the model with the weights saved at `path` in `format`
+    pub(crate) fn init_with_file<B: Backend>(
+        &self,
+        num_classes: usize,
+        path: impl Into<PathBuf>,
+        format: RecordFormat,
+        device: &B::Device,
+    ) -> Result<ResNet<B>, RecorderError> {
+        let record = format.load(path, device)?;
+        Ok(self.init::<B>(num_classes, device).load_record(record))
+    }
+}
+
+fn conv3x3<B: Backend>(
+    in_channels: usize,
+    out_channels: usize,
+    stride: usize,
+    device: &B::Device,
+) -> Conv2d<B> {
+    Conv2dConfig::new([in_channels, out_channels], [3, 3])
+        .with_stride([stride, stride])
+        .with_padding(PaddingConfig2d::Explicit(1, 1))
+        .with_bias(false)
+        .init(device)
+}
+
+/// A small ResNet: a conv stem, residual blocks and global average pooling
+#[derive(Debug, Module)]
+pub(crate) struct ResNet<B: Backend> {
+    stem: Conv2d<B>,
+    stem_norm: BatchNorm<B>,
+    blocks: Vec<ResidualBlock<B>>,
+    // Global average pooling, one value per channel
+    pool: AdaptiveAvgPool2d,
+    linear: Linear<B>,
+    activation: Relu,
+}
+
+impl<B: Backend> Classifier<B> for ResNet<B> {
+    fn forward(&self, images: Tensor<B, 3>) -> Tensor<B, 2> {
+        let [batch_size, height, width] = images.dims();
+        let x = images.reshape([batch_size, 1, height, width]);
+        let x = self.stem.forward(x);
+        let x = self.stem_norm.forward(x);
+        let mut x = self.activation.forward(x);
+
+        for block in &self.blocks {
+            x = block.forward(x);
+        }
+
+        let x = self.pool.forward(x);
+        let x = x.flatten::<2>(1, 3);
+        self.linear.forward(x)
+    }
+}
+
+impl<B: Backend> ResNet<B> {
+    /// Adds the same layers `forward` runs to `graph`, returning the logits
+    pub(crate) fn to_onnx(&self, graph: &mut OnnxGraph) -> String {
+        let x = graph.images();
+        let x = graph.conv2d(x, &self.stem);
+        let mut x = graph.batch_norm(x, &self.stem_norm);
+        x.name = graph.relu(&x.name);
+
+        for block in &self.blocks {
+            x = block.to_onnx(graph, x);
+        }
+
+        let x = graph.adaptive_avg_pool(x, &self.pool);
+        let x = graph.flatten(x);
+        graph.linear(&x, &self.linear)
+    }
+}
+
+/// Two 3x3 convolutions added onto the input, which is projected when the shape changes
+#[derive(Debug, Module)]
+pub(crate) struct ResidualBlock<B: Backend> {
+    conv1: Conv2d<B>,
+    norm1: BatchNorm<B>,
+    conv2: Conv2d<B>,
+    norm2: BatchNorm<B>,
+    shortcut: Option<Shortcut<B>>,
+    activation: Relu,
+}
+
+/// 1x1 convolution matching the skip connection to the block's output
+#[derive(Debug, Module)]
+pub(crate) struct Shortcut<B: Backend> {
+    conv: Conv2d<B>,
+    norm: BatchNorm<B>,
+}
+
+impl<B: Backend> ResidualBlock<B> {
+    fn new(in_channels: usize, out_channels: usize, stride: usize, device: &B::Device) -> Self {
+        let shortcut = (stride != 1 || in_channels != out_channels).then(|| Shortcut {
+            conv: Conv2dConfig::new([in_channels, out_channels], [1, 1])
+                .with_stride([stride, stride])
+                .with_bias(false)
+                .init(device),
+            norm: BatchNormConfig::new(out_channels).init(device),
+        });
+
+        Self {
+            conv1: conv3x3(in_channels, out_channels, stride, device),
+            norm1: BatchNormConfig::new(out_channels).init(device),
+            conv2: conv3x3(out_channels, out_channels, 1, device),
+            norm2: BatchNormConfig::new(out_channels).init(device),
+            shortcut,
+            activation: Relu::new(),
+        }
+    }
+
+    fn forward(&self, input: Tensor<B, 4>) -> Tensor<B, 4> {
+        let x = self.conv1.forward(input.clone());
+        let x = self.norm1.forward(x);
+        let x = self.activation.forward(x);
+        let x = self.conv2.forward(x);
+        let x = self.norm2.forward(x);
+
+        let skip = match &self.shortcut {
+            Some(shortcut) => shortcut.norm.forward(shortcut.conv.forward(input)),
+            None => input,
+        };
+
+        self.activation.forward(x + skip)
+    }
+
+    fn to_onnx(&self, graph: &mut OnnxGraph, input: FeatureMap) -> FeatureMap {
+        let x = graph.conv2d(input.clone(), &self.conv1);
+        let mut x = graph.batch_norm(x, &self.norm1);
+        x.name = graph.relu(&x.name);
+        let x = graph.conv2d(x, &self.conv2);
+        let mut x = graph.batch_norm(x, &self.norm2);
+
+        let skip = match &self.shortcut {
+            Some(shortcut) => {
+                let skip = graph.conv2d(input, &shortcut.conv);
+                graph.batch_norm(skip, &shortcut.norm)
+            }
+            None => input,
+        };
+
+        let sum = graph.add(&x.name, &skip.name);
+        x.name = graph.relu(&sum);
+        x
+    }
+}
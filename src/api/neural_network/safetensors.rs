@@ -0,0 +1,187 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// A tensor as it's stored in a safetensors file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StoredTensor {
+    pub(crate) shape: Vec<usize>,
+    pub(crate) values: StoredValues,
+}
+
+/// The dtypes this crate writes and reads
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StoredValues {
+    F32(Vec<f32>),
+    I8(Vec<i8>),
+}
+
+impl StoredValues {
+    fn len(&self) -> usize {
+        match self {
+            StoredValues::F32(values) => values.len(),
+            StoredValues::I8(values) => values.len(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HeaderEntry {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: [usize; 2],
+}
+
+/// Encodes the tensors in the safetensors format
+pub(crate) fn encode_safetensors(
+    tensors: &BTreeMap<String, StoredTensor>,
+) -> serde_json::Result<Vec<u8>> {
+    let mut header = serde_json::Map::new();
+    let mut data = Vec::new();
+
+    for (name, tensor) in tensors {
+        let start = data.len();
+        let dtype = match &tensor.values {
+            StoredValues::F32(values) => {
+                data.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+                "F32"
+            }
+            StoredValues::I8(values) => {
+                data.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+                "I8"
+            }
+        };
+        header.insert(
+            name.clone(),
+            serde_json::json!({
+                "dtype": dtype,
+                "shape": tensor.shape,
+                "data_offsets": [start, data.len()],
+            }),
+        );
+    }
+
+    // The data that follows the header starts 8-byte aligned
+    let mut header = serde_json::to_vec(&header)?;
+    header.resize(header.len().next_multiple_of(8), b' ');
+
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend(header);
+    bytes.extend(data);
+    Ok(bytes)
+}
+
+/// Reads the F32 and I8 tensors of a safetensors file. Every size in it is checked, so a
+/// corrupt file is an error rather than a panic
+pub(crate) fn decode_safetensors(bytes: &[u8]) -> Result<BTreeMap<String, StoredTensor>, String> {
+    let header_size = bytes
+        .get(..8)
+        .and_then(|size| size.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or("the file is too short")?;
+    let header_end = usize::try_from(header_size)
+        .ok()
+        .and_then(|size| size.checked_add(8))
+        .ok_or("the header size is out of range")?;
+    let header = bytes.get(8..header_end).ok_or("the header is cut off")?;
+    let data = &bytes[header_end..];
+
+    let header = serde_json::from_slice::<BTreeMap<String, serde_json::Value>>(header)
+        .map_err(|error| error.to_string())?;
+
+    let mut tensors = BTreeMap::new();
+    for (name, entry) in header {
+        if name == "__metadata__" {
+            continue;
+        }
+
+        let entry = serde_json::from_value::<HeaderEntry>(entry)
+            .map_err(|error| format!("{name}: {error}"))?;
+        let [start, end] = entry.data_offsets;
+        let bytes = data
+            .get(start..end)
+            .ok_or_else(|| format!("{name} is out of bounds"))?;
+
+        let (values, size) = match entry.dtype.as_str() {
+            "F32" => (
+                StoredValues::F32(
+                    bytes
+                        .chunks_exact(4)
+                        .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                        .collect(),
+                ),
+                4,
+            ),
+            "I8" => (
+                StoredValues::I8(bytes.iter().map(|value| *value as i8).collect()),
+                1,
+            ),
+            dtype => return Err(format!("{name} is {dtype}, only F32 and I8 are read")),
+        };
+        let len = entry
+            .shape
+            .iter()
+            .try_fold(1usize, |len, size| len.checked_mul(*size));
+        if len != Some(values.len()) || bytes.len() != values.len() * size {
+            return Err(format!("{name} doesn't match its shape {:?}", entry.shape));
+        }
+
+        tensors.insert(
+            name,
+            StoredTensor {
+                shape: entry.shape,
+                values,
+            },
+        );
+    }
+
+    Ok(tensors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tensors() -> BTreeMap<String, StoredTensor> {
+        BTreeMap::from([
+            (
+                "weight".to_owned(),
+                StoredTensor {
+                    shape: vec![2, 2],
+                    values: StoredValues::I8(vec![-127, 0, 5, 127]),
+                },
+            ),
+            (
+                "weight.scale".to_owned(),
+                StoredTensor {
+                    shape: vec![2, 1],
+                    values: StoredValues::F32(vec![0.5, 0.25]),
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn tensors_round_trip() {
+        let bytes = encode_safetensors(&tensors()).unwrap();
+        assert_eq!(decode_safetensors(&bytes).unwrap(), tensors());
+    }
+
+    #[test]
+    fn corrupt_files_are_errors() {
+        let bytes = encode_safetensors(&tensors()).unwrap();
+
+        let mut huge_header = bytes.clone();
+        huge_header[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut past_the_end = bytes.clone();
+        past_the_end[..8].copy_from_slice(&(bytes.len() as u64).to_le_bytes());
+
+        for bytes in [
+            &bytes[..4],
+            &huge_header[..],
+            &past_the_end[..],
+            &bytes[..bytes.len() - 1],
+        ] {
+            assert!(decode_safetensors(bytes).is_err());
+        }
+    }
+}
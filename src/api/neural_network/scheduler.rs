@@ -0,0 +1,281 @@
+use burn::{lr_scheduler::LrScheduler, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// How the learning rate changes over training, starting from `learning_rate` in the config.
+/// Picked with `kind` in the `scheduler` config section.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum SchedulerConfig {
+    /// The learning rate never changes
+    #[default]
+    Constant,
+    /// Multiplies the learning rate by `gamma` every `step_size` steps
+    Step { step_size: usize, gamma: f64 },
+    /// Multiplies the learning rate by `gamma` every step
+    Exponential { gamma: f64 },
+    /// Anneals from the learning rate down to `min_lr` over the whole run
+    Cosine {
+        #[serde(default)]
+        min_lr: f64,
+    },
+    /// Ramps up linearly for `warmup_steps`, then anneals like `Cosine`
+    WarmupCosine {
+        warmup_steps: usize,
+        #[serde(default)]
+        min_lr: f64,
+    },
+    /// Anneals up to the learning rate for the first `pct_start` of the run, then far below it
+    OneCycle {
+        #[serde(default = "one_cycle_pct_start")]
+        pct_start: f64,
+        /// The run starts at `learning_rate / div_factor`
+        #[serde(default = "one_cycle_div_factor")]
+        div_factor: f64,
+        /// The run ends at `learning_rate / div_factor / final_div_factor`
+        #[serde(default = "one_cycle_final_div_factor")]
+        final_div_factor: f64,
+    },
+}
+
+fn one_cycle_pct_start() -> f64 {
+    0.3
+}
+
+fn one_cycle_div_factor() -> f64 {
+    25.0
+}
+
+fn one_cycle_final_div_factor() -> f64 {
+    1.0e4
+}
+
+impl SchedulerConfig {
+    /// `total_steps` is the number of batches over every epoch, used by the annealing schedules
+    pub(crate) fn init(&self, learning_rate: f64, total_steps: usize) -> Scheduler {
+        Scheduler {
+            config: self.clone(),
+            learning_rate,
+            total_steps: total_steps.max(1),
+            step: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Scheduler {
+    config: SchedulerConfig,
+    learning_rate: f64,
+    total_steps: usize,
+    step: usize,
+}
+
+impl Scheduler {
+    fn learning_rate(&self) -> f64 {
+        let lr = self.learning_rate;
+        let step = self.step.min(self.total_steps) as f64;
+        let total = self.total_steps as f64;
+
+        match self.config {
+            SchedulerConfig::Constant => lr,
+            SchedulerConfig::Step { step_size, gamma } => {
+                lr * gamma.powi((self.step / step_size.max(1)) as i32)
+            }
+            SchedulerConfig::Exponential { gamma } => lr * gamma.powf(self.step as f64),
+            SchedulerConfig::Cosine { min_lr } => anneal(lr, min_lr, step / total),
+            SchedulerConfig::WarmupCosine {
+                warmup_steps,
+                min_lr,
+            } => {
+                if self.step < warmup_steps {
+                    lr * (self.step + 1) as f64 / warmup_steps as f64
+                } else {
+                    let warmup = warmup_steps as f64;
+                    anneal(lr, min_lr, (step - warmup) / (total - warmup).max(1.0))
+                }
+            }
+            SchedulerConfig::OneCycle {
+                pct_start,
+                div_factor,
+                final_div_factor,
+            } => {
+                let initial_lr = lr / div_factor;
+                let peak = (pct_start * total).max(1.0);
+                if step < peak {
+                    anneal(initial_lr, lr, step / peak)
+                } else {
+                    let final_lr = initial_lr / final_div_factor;
+                    anneal(lr, final_lr, (step - peak) / (total - peak).max(1.0))
+                }
+            }
+        }
+    }
+}
+
+/// Cosine curve from `start` at `progress` 0 to `end` at 1
+fn anneal(start: f64, end: f64, progress: f64) -> f64 {
+    end + (start - end) * (1.0 + (PI * progress.clamp(0.0, 1.0)).cos()) / 2.0
+}
+
+impl LrScheduler for Scheduler {
+    // Only the step is saved, everything else comes from the config when resuming
+    type Record<B: Backend> = usize;
+
+    fn step(&mut self) -> f64 {
+        let lr = self.learning_rate();
+        self.step += 1;
+        lr
+    }
+
+    fn to_record<B: Backend>(&self) -> Self::Record<B> {
+        self.step
+    }
+
+    fn load_record<B: Backend>(mut self, record: Self::Record<B>) -> Self {
+        self.step = record;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The learning rates of the first `steps` steps, starting from 1
+    fn rates(config: SchedulerConfig, total_steps: usize, steps: usize) -> Vec<f64> {
+        let mut scheduler = config.init(1.0, total_steps);
+        (0..steps).map(|_| scheduler.step()).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    /// How far along a cosine curve from 1 to 0 is at `progress`
+    fn cosine(progress: f64) -> f64 {
+        (1.0 + (PI * progress).cos()) / 2.0
+    }
+
+    #[test]
+    fn constant_never_changes() {
+        let rates = rates(SchedulerConfig::Constant, 10, 11);
+        assert!(rates.iter().all(|rate| *rate == 1.0));
+    }
+
+    #[test]
+    fn step_decays_every_step_size() {
+        let rates = rates(
+            SchedulerConfig::Step {
+                step_size: 3,
+                gamma: 0.5,
+            },
+            10,
+            10,
+        );
+        assert_close(rates[0], 1.0);
+        assert_close(rates[2], 1.0);
+        assert_close(rates[3], 0.5);
+        assert_close(rates[9], 0.125);
+    }
+
+    #[test]
+    fn exponential_decays_every_step() {
+        let rates = rates(SchedulerConfig::Exponential { gamma: 0.5 }, 10, 10);
+        assert_close(rates[0], 1.0);
+        assert_close(rates[1], 0.5);
+        assert_close(rates[9], 0.5f64.powi(9));
+    }
+
+    #[test]
+    fn cosine_anneals_to_min_lr() {
+        let rates = rates(SchedulerConfig::Cosine { min_lr: 0.1 }, 10, 12);
+        assert_close(rates[0], 1.0);
+        assert_close(rates[5], 0.55);
+        assert_close(rates[9], 0.1 + 0.9 * cosine(0.9));
+        // Steps past the end stay at the minimum
+        assert_close(rates[10], 0.1);
+        assert_close(rates[11], 0.1);
+    }
+
+    #[test]
+    fn warmup_cosine_ramps_up_then_anneals() {
+        let rates = rates(
+            SchedulerConfig::WarmupCosine {
+                warmup_steps: 4,
+                min_lr: 0.0,
+            },
+            10,
+            11,
+        );
+        assert_close(rates[0], 0.25);
+        assert_close(rates[1], 0.5);
+        // The last warmup step reaches the learning rate, and annealing starts from it
+        assert_close(rates[3], 1.0);
+        assert_close(rates[4], 1.0);
+        assert_close(rates[9], cosine(5.0 / 6.0));
+        assert_close(rates[10], 0.0);
+    }
+
+    #[test]
+    fn one_cycle_peaks_after_pct_start() {
+        let rates = rates(
+            SchedulerConfig::OneCycle {
+                pct_start: 0.3,
+                div_factor: 10.0,
+                final_div_factor: 100.0,
+            },
+            10,
+            11,
+        );
+        assert_close(rates[0], 0.1);
+        assert_close(rates[3], 1.0);
+        assert!(rates[..3].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(rates[3..].windows(2).all(|pair| pair[0] > pair[1]));
+        assert_close(rates[9], 0.001 + 0.999 * cosine(6.0 / 7.0));
+        assert_close(rates[10], 0.001);
+    }
+
+    #[test]
+    fn tiny_runs_have_finite_rates() {
+        let configs = [
+            SchedulerConfig::Constant,
+            SchedulerConfig::Step {
+                step_size: 0,
+                gamma: 0.5,
+            },
+            SchedulerConfig::Exponential { gamma: 0.5 },
+            SchedulerConfig::Cosine { min_lr: 0.0 },
+            SchedulerConfig::WarmupCosine {
+                warmup_steps: 0,
+                min_lr: 0.0,
+            },
+            SchedulerConfig::WarmupCosine {
+                warmup_steps: 5,
+                min_lr: 0.0,
+            },
+            SchedulerConfig::OneCycle {
+                pct_start: 0.0,
+                div_factor: 25.0,
+                final_div_factor: 1.0e4,
+            },
+            SchedulerConfig::OneCycle {
+                pct_start: 0.3,
+                div_factor: 25.0,
+                final_div_factor: 1.0e4,
+            },
+        ];
+
+        for config in configs {
+            for total_steps in [0, 1] {
+                let rates = rates(config.clone(), total_steps, 3);
+                assert!(
+                    rates.iter().all(|rate| rate.is_finite() && *rate >= 0.0),
+                    "{config:?} over {total_steps} steps gave {rates:?}"
+                );
+            }
+        }
+    }
+}
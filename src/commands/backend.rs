@@ -0,0 +1,209 @@
+use burn::{
+    backend::{Autodiff, NdArray, ndarray::NdArrayDevice},
+    prelude::*,
+    tensor::backend::AutodiffBackend,
+};
+use std::sync::{Mutex, PoisonError};
+
+/// Backends selectable with `--backend`, anything but ndarray needs its cargo feature
+#[derive(clap::ValueEnum, Clone, Debug, Default)]
+pub(crate) enum FlagBackend {
+    #[default]
+    Ndarray,
+    #[cfg(feature = "cuda")]
+    Cuda,
+    #[cfg(feature = "wgpu")]
+    Wgpu,
+    #[cfg(feature = "candle-cpu")]
+    CandleCpu,
+    #[cfg(feature = "tch-cpu")]
+    TchCpu,
+}
+
+impl std::fmt::Display for FlagBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlagBackend::Ndarray => f.write_str("ndarray"),
+            #[cfg(feature = "cuda")]
+            FlagBackend::Cuda => f.write_str("cuda"),
+            #[cfg(feature = "wgpu")]
+            FlagBackend::Wgpu => f.write_str("wgpu"),
+            #[cfg(feature = "candle-cpu")]
+            FlagBackend::CandleCpu => f.write_str("candle-cpu"),
+            #[cfg(feature = "tch-cpu")]
+            FlagBackend::TchCpu => f.write_str("tch-cpu"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum BackendError {
+    #[error("The {backend} backend is not available: {reason}")]
+    Unavailable {
+        backend: FlagBackend,
+        reason: String,
+    },
+}
+
+/// Work that can run on any backend, handed to [`dispatch`].
+///
+/// Tasks that don't need gradients can use `B::InnerBackend`, which shares the same device.
+pub(crate) trait BackendTask {
+    type Output;
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<Self::Output>;
+}
+
+/// Runs `task` on the backend picked with `--backend`
+pub(crate) fn dispatch<T: BackendTask>(backend: &FlagBackend, task: T) -> crate::Result<T::Output> {
+    match backend {
+        FlagBackend::Ndarray => {
+            run::<Autodiff<NdArray>, T>(backend, NdArrayDevice::default(), task)
+        }
+        #[cfg(feature = "cuda")]
+        FlagBackend::Cuda => run::<Autodiff<burn::backend::Cuda>, T>(
+            backend,
+            burn::backend::cuda::CudaDevice::default(),
+            task,
+        ),
+        #[cfg(feature = "wgpu")]
+        FlagBackend::Wgpu => run::<Autodiff<burn::backend::Wgpu>, T>(
+            backend,
+            burn::backend::wgpu::WgpuDevice::default(),
+            task,
+        ),
+        #[cfg(feature = "candle-cpu")]
+        FlagBackend::CandleCpu => run::<Autodiff<burn::backend::Candle>, T>(
+            backend,
+            burn::backend::candle::CandleDevice::Cpu,
+            task,
+        ),
+        #[cfg(feature = "tch-cpu")]
+        FlagBackend::TchCpu => run::<Autodiff<burn::backend::LibTorch>, T>(
+            backend,
+            burn::backend::libtorch::LibTorchDevice::Cpu,
+            task,
+        ),
+    }
+}
+
+fn run<B, T>(backend: &FlagBackend, device: B::Device, task: T) -> crate::Result<T::Output>
+where
+    B: AutodiffBackend,
+    T: BackendTask,
+{
+    available(backend, || {
+        Tensor::<B, 1>::zeros([1], &device).into_data();
+    })?;
+
+    task.run::<B>(device)
+}
+
+/// Runs `probe` on the backend, turning a failure into [`BackendError::Unavailable`]
+fn available(backend: &FlagBackend, probe: impl FnOnce()) -> Result<(), BackendError> {
+    probe_device(probe).map_err(|reason| BackendError::Unavailable {
+        backend: backend.clone(),
+        reason,
+    })
+}
+
+/// The panic hook is process wide, so everything that swaps it holds this lock
+static PANIC_HOOK: Mutex<()> = Mutex::new(());
+
+/// Makes sure a device can actually run something.
+///
+/// GPU backends panic on their first operation when there is no driver or device, so we run a
+/// tiny one up front and turn the panic into an error message.
+fn probe_device(probe: impl FnOnce()) -> Result<(), String> {
+    let _hook = PANIC_HOOK.lock().unwrap_or_else(PoisonError::into_inner);
+    run_silenced(probe)
+}
+
+/// Runs `probe` with the panic hook quiet, the error is reported by the caller instead.
+/// Callers hold [`PANIC_HOOK`]
+fn run_silenced(probe: impl FnOnce()) -> Result<(), String> {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(probe));
+    std::panic::set_hook(hook);
+
+    result.map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| {
+                payload
+                    .downcast_ref::<&str>()
+                    .map(|reason| reason.to_string())
+            })
+            .unwrap_or_else(|| "no usable device found".to_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    struct Sum(Vec<f32>);
+
+    impl BackendTask for Sum {
+        type Output = f32;
+
+        fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<f32> {
+            let values = Tensor::<B, 1>::from_floats(self.0.as_slice(), &device);
+            Ok(values.sum().into_scalar().elem())
+        }
+    }
+
+    #[test]
+    fn dispatch_runs_the_task() {
+        let sum = dispatch(&FlagBackend::Ndarray, Sum(vec![1.0, 2.0, 3.0])).unwrap();
+        assert_eq!(sum, 6.0);
+    }
+
+    #[test]
+    fn probe_passes_on_a_working_device() {
+        assert!(probe_device(|| ()).is_ok());
+    }
+
+    #[test]
+    fn unavailable_device_is_an_error() {
+        let error: crate::Result<()> =
+            available(&FlagBackend::Ndarray, || panic!("no CUDA driver found")).map_err(Into::into);
+
+        assert_eq!(
+            probe_device(|| panic!("{} devices", 0)),
+            Err("0 devices".to_owned())
+        );
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "The ndarray backend is not available: no CUDA driver found"
+        );
+    }
+
+    #[test]
+    fn probing_restores_the_panic_hook() {
+        // Held throughout, so no other probe swaps the hook in between
+        let _hook = PANIC_HOOK.lock().unwrap_or_else(PoisonError::into_inner);
+        let panics = Arc::new(AtomicUsize::new(0));
+        let counter = panics.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let reason = run_silenced(|| panic!("no CUDA driver found"));
+        let silent = panics.load(Ordering::SeqCst);
+        let _ = std::panic::catch_unwind(|| panic!("after probing"));
+        let restored = panics.load(Ordering::SeqCst);
+        std::panic::set_hook(previous);
+
+        assert_eq!(reason, Err("no CUDA driver found".to_owned()));
+        assert_eq!(silent, 0);
+        assert_eq!(restored, 1);
+    }
+}
@@ -0,0 +1,102 @@
+use super::*;
+use burn::{prelude::*, tensor::backend::AutodiffBackend};
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub(crate) struct Arguments {
+    #[arg(long, default_value_t = FlagBackend::default())]
+    backend: FlagBackend,
+    /// The trained model dir, typically output
+    #[arg(long, default_value_t = String::from("./output"))]
+    model_dir: String,
+    /// The format the weights are rewritten in
+    #[arg(long, value_enum)]
+    to: FlagRecordFormat,
+    /// How the weights are saved now, the format in the model's config when unset
+    #[arg(long, value_enum)]
+    from: Option<FlagRecordFormat>,
+    /// Where the converted model is saved, the model dir itself by default
+    #[arg(long)]
+    output_dir: Option<String>,
+}
+
+pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
+    let model_dir = PathBuf::from(&args.model_dir);
+    let mut config = TrainingConfig::load(model_dir.join("model_config.json").to_str().unwrap())?;
+    if config.quantization.is_some() {
+        return Err(color_eyre::eyre::eyre!(
+            "{} holds int8 weights, convert the float model instead",
+            model_dir.display()
+        ));
+    }
+    if let Some(format) = args.from {
+        config.record_format = format.into();
+    }
+    let output_dir = args
+        .output_dir
+        .as_ref()
+        .map_or_else(|| model_dir.clone(), PathBuf::from);
+
+    dispatch(
+        &args.backend,
+        Convert {
+            model_dir,
+            config,
+            format: args.to.into(),
+            output_dir,
+        },
+    )
+}
+
+struct Convert {
+    model_dir: PathBuf,
+    config: TrainingConfig,
+    format: RecordFormat,
+    output_dir: PathBuf,
+}
+
+impl BackendTask for Convert {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        convert::<B::InnerBackend>(self, device)
+    }
+}
+
+fn convert<B: Backend>(task: Convert, device: B::Device) -> crate::Result<()> {
+    let mut config = task.config;
+    let model = config
+        .load_model::<B>(task.model_dir.join("model"), &device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load the trained model: {error}"))?;
+    let previous = config.record_format;
+
+    std::fs::create_dir_all(&task.output_dir)?;
+    model
+        .save_weights(task.output_dir.join("model"), task.format)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to save the model: {error}"))?;
+
+    config.record_format = task.format;
+    config.model = config.model.resaved();
+    config.save(task.output_dir.join("model_config.json").to_str().unwrap())?;
+
+    if task.output_dir != task.model_dir {
+        if let Some(classes) = load_classes(&task.model_dir)? {
+            save_classes(task.output_dir.to_str().unwrap(), &classes)?;
+        }
+    } else if previous.extension() != task.format.extension() {
+        // Only the weights the config points at are kept
+        std::fs::remove_file(
+            task.model_dir
+                .join("model")
+                .with_extension(previous.extension()),
+        )?;
+    }
+
+    println!(
+        "Converted the model from {previous:?} to {:?} in {}",
+        task.format,
+        task.output_dir.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use super::TrainingConfig;
+use crate::api::neural_network::{
+    ImageFolderConfig, ImageFolderDataset, ImageItem, MnistBatch, MnistBatcher, MnistIdxDataset,
+};
+use burn::{
+    data::{
+        dataloader::{DataLoader, DataLoaderBuilder},
+        dataset::{
+            Dataset,
+            transform::{Mapper, MapperDataset, PartialDataset, ShuffledDataset},
+            vision::{MnistDataset, MnistItem},
+        },
+    },
+    prelude::*,
+};
+use std::sync::Arc;
+
+pub(super) type Loader<B> = Arc<dyn DataLoader<B, MnistBatch<B>>>;
+
+pub(super) type ItemDataset = Arc<dyn Dataset<ImageItem>>;
+
+/// Part of the configured data
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(super) enum Split {
+    /// What's left of the training data after the validation hold-out
+    Train,
+    /// The seeded `validation_split` held out from the training data
+    Valid,
+    Test,
+}
+
+/// The configured data split the same way for training and evaluation
+pub(super) struct Datasets {
+    pub(super) train: ItemDataset,
+    pub(super) valid: ItemDataset,
+    pub(super) test: ItemDataset,
+    /// Class names when the dataset has them
+    pub(super) classes: Option<Vec<String>>,
+}
+
+impl Datasets {
+    /// Loads the data, holding out a seeded `validation_split` of the training data
+    pub(super) fn load(config: &TrainingConfig) -> crate::Result<Self> {
+        let (train, classes) = training_data(config)?;
+        let (train, valid) = hold_out(config, train)?;
+
+        Ok(Self {
+            train,
+            valid,
+            test: test_data(config, classes.clone())?,
+            classes,
+        })
+    }
+
+    /// Loads just `split`, without reading the data the other splits come from
+    pub(super) fn load_split(config: &TrainingConfig, split: Split) -> crate::Result<ItemDataset> {
+        match split {
+            Split::Train => Ok(hold_out(config, training_data(config)?.0)?.0),
+            Split::Valid => Ok(hold_out(config, training_data(config)?.0)?.1),
+            Split::Test => test_data(config, None),
+        }
+    }
+}
+
+/// Where the configured data is read from
+enum Source<'a> {
+    ImageFolder(&'a ImageFolderConfig),
+    Idx(&'a str),
+    Download,
+}
+
+impl<'a> Source<'a> {
+    fn of(config: &'a TrainingConfig) -> crate::Result<Self> {
+        match (&config.image_folder, &config.data_dir) {
+            (Some(_), Some(_)) => Err(color_eyre::eyre::eyre!(
+                "`image_folder` and `data_dir` can't be used together"
+            )),
+            (Some(folder), None) => Ok(Source::ImageFolder(folder)),
+            (None, Some(dir)) => Ok(Source::Idx(dir)),
+            (None, None) => Ok(Source::Download),
+        }
+    }
+}
+
+/// All of the training data, with the class names when the dataset has them
+fn training_data(config: &TrainingConfig) -> crate::Result<(ItemDataset, Option<Vec<String>>)> {
+    Ok(match Source::of(config)? {
+        Source::ImageFolder(folder) => {
+            let train = ImageFolderDataset::new(&folder.train, &folder.preprocessor())?;
+            let classes = train.classes().to_vec();
+            (Arc::new(train), Some(classes))
+        }
+        Source::Idx(dir) => (mnist(MnistIdxDataset::train(dir)?), None),
+        Source::Download => (mnist(MnistDataset::train()), None),
+    })
+}
+
+/// The test data. Image folders take their classes from the training folder, listed without
+/// reading its images when `classes` isn't given
+fn test_data(config: &TrainingConfig, classes: Option<Vec<String>>) -> crate::Result<ItemDataset> {
+    Ok(match Source::of(config)? {
+        Source::ImageFolder(folder) => {
+            let classes = match classes {
+                Some(classes) => classes,
+                None => ImageFolderDataset::find_classes(&folder.train)?,
+            };
+            Arc::new(ImageFolderDataset::with_classes(
+                &folder.test,
+                classes,
+                &folder.preprocessor(),
+            )?)
+        }
+        Source::Idx(dir) => mnist(MnistIdxDataset::test(dir)?),
+        Source::Download => mnist(MnistDataset::test()),
+    })
+}
+
+/// Shuffles `train` with the seed and splits off `validation_split` of it, as `(train, valid)`
+fn hold_out(
+    config: &TrainingConfig,
+    train: ItemDataset,
+) -> crate::Result<(ItemDataset, ItemDataset)> {
+    if config.validation_split <= 0.0 || config.validation_split >= 1.0 {
+        return Err(color_eyre::eyre::eyre!(
+            "`validation_split` must be between 0 and 1, got {}",
+            config.validation_split
+        ));
+    }
+
+    let train = Arc::new(ShuffledDataset::with_seed(train, config.seed));
+    let num_valid = (train.len() as f64 * config.validation_split).round() as usize;
+
+    Ok((
+        Arc::new(PartialDataset::new(train.clone(), num_valid, train.len())),
+        Arc::new(PartialDataset::new(train.clone(), 0, num_valid)),
+    ))
+}
+
+struct MnistToImage;
+
+impl Mapper<MnistItem, ImageItem> for MnistToImage {
+    fn map(&self, item: &MnistItem) -> ImageItem {
+        item.clone().into()
+    }
+}
+
+fn mnist(dataset: impl Dataset<MnistItem> + 'static) -> ItemDataset {
+    Arc::new(MapperDataset::new(dataset, MnistToImage))
+}
+
+pub(super) fn dataloader<B: Backend>(
+    config: &TrainingConfig,
+    batcher: MnistBatcher,
+    dataset: ItemDataset,
+) -> Loader<B> {
+    DataLoaderBuilder::new(batcher)
+        .batch_size(config.batch_size)
+        .shuffle(config.seed)
+        .num_workers(config.num_workers)
+        .build(dataset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn the_test_split_leaves_the_training_images_alone() {
+        let root = std::env::temp_dir().join(format!("split-test-{}", std::process::id()));
+        for class in ["a", "b"] {
+            std::fs::create_dir_all(root.join("train").join(class)).unwrap();
+            std::fs::create_dir_all(root.join("test").join(class)).unwrap();
+            GrayImage::from_pixel(8, 8, Luma([200]))
+                .save(root.join("test").join(class).join("0.png"))
+                .unwrap();
+        }
+        // Only decoding the training images would notice this
+        std::fs::write(root.join("train").join("a").join("0.png"), b"not a png").unwrap();
+
+        let folder = ImageFolderConfig::new(
+            root.join("train").display().to_string(),
+            root.join("test").display().to_string(),
+        );
+        let config = TrainingConfig::builder().image_folder(folder).build();
+        let test = Datasets::load_split(&config, Split::Test);
+        let all = Datasets::load(&config);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let test = test.unwrap();
+        assert_eq!(test.len(), 2);
+        assert_eq!(
+            [test.get(0).unwrap().label, test.get(1).unwrap().label],
+            [0, 1]
+        );
+        assert!(all.is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use super::{
+    data::{Datasets, ItemDataset, Loader, Split, dataloader},
+    *,
+};
+use crate::api::neural_network::{Classifier, ImageFolderDataset, MnistBatcher, Network};
+use burn::{prelude::*, tensor::backend::AutodiffBackend};
+use serde::Serialize;
+use std::{path::PathBuf, sync::Arc};
+
+#[derive(clap::Args)]
+pub(crate) struct Arguments {
+    #[arg(long, default_value_t = FlagBackend::default())]
+    backend: FlagBackend,
+    /// The trained model dir, typically output
+    #[arg(long, default_value_t = String::from("./output"))]
+    model_dir: String,
+    /// Which part of the data the model was trained with to score on
+    #[arg(long, value_enum, default_value_t = Split::Test)]
+    split: Split,
+    /// Score on a `root/<class_name>/*.png` image tree instead of a split
+    #[arg(long, conflicts_with = "split")]
+    image_folder: Option<String>,
+    /// Read MNIST from the IDX files in this directory instead of downloading it
+    #[arg(long)]
+    data_dir: Option<String>,
+    /// How many images are run through the model at once
+    #[arg(long, default_value_t = 64)]
+    batch_size: usize,
+    /// Where the JSON report is written, `evaluation.json` in the model dir by default
+    #[arg(long)]
+    report: Option<String>,
+}
+
+pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
+    let model_dir = PathBuf::from(&args.model_dir);
+    let mut config = TrainingConfig::load(model_dir.join("model_config.json").to_str().unwrap())?;
+    let classes = class_names(load_classes(&model_dir)?, config.model.num_classes());
+
+    if let Some(data_dir) = &args.data_dir {
+        config.data_dir = Some(data_dir.clone());
+    }
+    config.batch_size = args.batch_size.max(1);
+
+    let dataset = match &args.image_folder {
+        Some(root) => Arc::new(ImageFolderDataset::with_classes(
+            root,
+            classes.clone(),
+            &config.preprocessor(),
+        )?),
+        None => Datasets::load_split(&config, args.split)?,
+    };
+
+    let report = match &args.report {
+        Some(path) => PathBuf::from(path),
+        None => model_dir.join("evaluation.json"),
+    };
+
+    dispatch(
+        &args.backend,
+        Evaluate {
+            model_dir,
+            config,
+            classes,
+            dataset,
+            report,
+        },
+    )
+}
+
+struct Evaluate {
+    model_dir: PathBuf,
+    config: TrainingConfig,
+    classes: Vec<String>,
+    dataset: ItemDataset,
+    report: PathBuf,
+}
+
+impl BackendTask for Evaluate {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        evaluate::<B::InnerBackend>(self, device)
+    }
+}
+
+fn evaluate<B: Backend>(task: Evaluate, device: B::Device) -> crate::Result<()> {
+    let model = task
+        .config
+        .load_model::<B>(task.model_dir.join("model"), &device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load the trained model: {error}"))?;
+    let loader = dataloader(&task.config, MnistBatcher::default(), task.dataset);
+
+    let evaluation = Evaluation::run(&model, loader, task.classes)?;
+    evaluation.print();
+
+    std::fs::write(&task.report, serde_json::to_string_pretty(&evaluation)?)?;
+    println!("\nReport written to {}", task.report.display());
+
+    Ok(())
+}
+
+/// The model's class names, class indices for models without named classes like MNIST digits
+pub(super) fn class_names(classes: Option<Vec<String>>, num_classes: usize) -> Vec<String> {
+    classes.unwrap_or_else(|| (0..num_classes).map(|index| index.to_string()).collect())
+}
+
+#[derive(Serialize)]
+pub(super) struct Evaluation {
+    pub(super) items: usize,
+    pub(super) loss: f64,
+    pub(super) accuracy: f64,
+    classes: Vec<ClassMetrics>,
+    /// Rows are the true class, columns the predicted one, in the order of `classes`
+    confusion_matrix: Vec<Vec<usize>>,
+}
+
+#[derive(Serialize)]
+struct ClassMetrics {
+    class: String,
+    precision: f64,
+    recall: f64,
+    f1: f64,
+    /// Images of this class in the data
+    support: usize,
+}
+
+impl Evaluation {
+    /// Runs the model over every batch once. Labels outside of `classes` are an error rather
+    /// than a wrong report
+    pub(super) fn run<B: Backend>(
+        model: &Network<B>,
+        dataloader: Loader<B>,
+        classes: Vec<String>,
+    ) -> crate::Result<Self> {
+        let mut confusion_matrix = vec![vec![0; classes.len()]; classes.len()];
+        let mut loss = 0.0;
+
+        for batch in dataloader.iter() {
+            let [batch_size] = batch.targets.dims();
+            // Checked up front, the loss would index out of bounds too
+            let targets = batch
+                .targets
+                .to_data()
+                .iter::<i64>()
+                .map(|target| {
+                    usize::try_from(target)
+                        .ok()
+                        .filter(|target| *target < classes.len())
+                        .ok_or_else(|| {
+                            color_eyre::eyre::eyre!(
+                                "Found label {target}, the model only has {} classes",
+                                classes.len()
+                            )
+                        })
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+            let output = model.forward_classification(batch.images, batch.targets);
+
+            loss += output.loss.into_scalar().elem::<f64>() * batch_size as f64;
+
+            let predictions = output.output.argmax(1).into_data();
+            for (target, prediction) in targets.into_iter().zip(predictions.iter::<i64>()) {
+                confusion_matrix[target][prediction as usize] += 1;
+            }
+        }
+
+        Ok(Self::from_confusion_matrix(classes, confusion_matrix, loss))
+    }
+
+    /// Scores every class from the counts, `total_loss` is summed over every item
+    fn from_confusion_matrix(
+        classes: Vec<String>,
+        confusion_matrix: Vec<Vec<usize>>,
+        total_loss: f64,
+    ) -> Self {
+        let items = confusion_matrix.iter().flatten().sum::<usize>();
+        let correct = (0..classes.len())
+            .map(|index| confusion_matrix[index][index])
+            .sum::<usize>();
+
+        let classes = classes
+            .into_iter()
+            .enumerate()
+            .map(|(index, class)| {
+                let true_positives = confusion_matrix[index][index] as f64;
+                let support = confusion_matrix[index].iter().sum::<usize>();
+                let predicted = confusion_matrix.iter().map(|row| row[index]).sum::<usize>();
+
+                let precision = ratio(true_positives, predicted as f64);
+                let recall = ratio(true_positives, support as f64);
+                ClassMetrics {
+                    class,
+                    precision,
+                    recall,
+                    f1: ratio(2.0 * precision * recall, precision + recall),
+                    support,
+                }
+            })
+            .collect();
+
+        Self {
+            items,
+            loss: ratio(total_loss, items as f64),
+            accuracy: ratio(correct as f64, items as f64),
+            classes,
+            confusion_matrix,
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "accuracy {:.4}, loss {:.4} over {} images\n",
+            self.accuracy, self.loss, self.items
+        );
+
+        let class_width = self
+            .classes
+            .iter()
+            .map(|class| class.class.len())
+            .max()
+            .unwrap_or_default()
+            .max("class".len());
+        println!(
+            "{:<class_width$}  {:>9}  {:>9}  {:>9}  {:>9}",
+            "class", "precision", "recall", "f1", "support"
+        );
+        for class in &self.classes {
+            println!(
+                "{:<class_width$}  {:>9.4}  {:>9.4}  {:>9.4}  {:>9}",
+                class.class, class.precision, class.recall, class.f1, class.support
+            );
+        }
+
+        // Wide enough for any class name and count
+        let cell_width = self
+            .confusion_matrix
+            .iter()
+            .flatten()
+            .map(|count| count.to_string().len())
+            .chain(self.classes.iter().map(|class| class.class.len()))
+            .max()
+            .unwrap_or_default();
+        println!("\nconfusion matrix (rows: true class, columns: predicted class)");
+        let header = self
+            .classes
+            .iter()
+            .map(|class| format!("{:>cell_width$}", class.class))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!("{:<class_width$}  {header}", "");
+        for (class, row) in self.classes.iter().zip(&self.confusion_matrix) {
+            let row = row
+                .iter()
+                .map(|count| format!("{count:>cell_width$}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!("{:<class_width$}  {row}", class.class);
+        }
+    }
+}
+
+/// `numerator / denominator`, 0 instead of NaN when nothing was counted
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0.0 {
+        numerator / denominator
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::neural_network::ImageItem;
+    use burn::{backend::NdArray, data::dataset::InMemDataset};
+
+    fn evaluate(labels: &[usize]) -> crate::Result<Evaluation> {
+        let config = TrainingConfig::builder().num_workers(1).build();
+        let model = config.init_model::<NdArray>(&Default::default());
+        let items = labels
+            .iter()
+            .map(|label| ImageItem {
+                pixels: vec![0.0; 28 * 28],
+                width: 28,
+                height: 28,
+                label: *label,
+            })
+            .collect();
+        let loader = dataloader(
+            &config,
+            MnistBatcher::default(),
+            Arc::new(InMemDataset::new(items)),
+        );
+
+        Evaluation::run(&model, loader, class_names(None, 10))
+    }
+
+    #[test]
+    fn every_item_is_counted() {
+        let evaluation = evaluate(&[0, 3, 3, 9]).unwrap();
+        assert_eq!(evaluation.items, 4);
+        assert_eq!(evaluation.classes[3].support, 2);
+        assert_eq!(
+            evaluation.confusion_matrix.iter().flatten().sum::<usize>(),
+            4
+        );
+    }
+
+    #[test]
+    fn labels_outside_the_classes_are_an_error() {
+        let error = evaluate(&[1, 12]).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Found label 12, the model only has 10 classes"
+        );
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn metrics_match_the_confusion_matrix() {
+        let classes = ["a", "b", "c"].map(String::from).to_vec();
+        // Nothing is ever predicted as c
+        let confusion_matrix = vec![vec![3, 1, 0], vec![1, 2, 0], vec![1, 1, 0]];
+        let evaluation = Evaluation::from_confusion_matrix(classes, confusion_matrix, 4.5);
+
+        assert_eq!(evaluation.items, 9);
+        assert_close(evaluation.loss, 0.5);
+        assert_close(evaluation.accuracy, 5.0 / 9.0);
+
+        let expected = [
+            ("a", 3.0 / 5.0, 3.0 / 4.0, 2.0 / 3.0, 4),
+            ("b", 2.0 / 4.0, 2.0 / 3.0, 4.0 / 7.0, 3),
+            ("c", 0.0, 0.0, 0.0, 2),
+        ];
+        for (metrics, (class, precision, recall, f1, support)) in
+            evaluation.classes.iter().zip(expected)
+        {
+            assert_eq!(metrics.class, class);
+            assert_close(metrics.precision, precision);
+            assert_close(metrics.recall, recall);
+            assert_close(metrics.f1, f1);
+            assert_eq!(metrics.support, support);
+        }
+    }
+
+    #[test]
+    fn empty_data_scores_zero() {
+        let evaluation =
+            Evaluation::from_confusion_matrix(class_names(None, 2), vec![vec![0; 2]; 2], 0.0);
+        assert_eq!(evaluation.items, 0);
+        assert_eq!((evaluation.loss, evaluation.accuracy), (0.0, 0.0));
+        assert!(evaluation.classes.iter().all(|class| class.f1 == 0.0));
+    }
+}
@@ -0,0 +1,83 @@
+use super::{evaluate::class_names, *};
+use burn::{prelude::*, tensor::backend::AutodiffBackend};
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub(crate) struct Arguments {
+    #[arg(long, default_value_t = FlagBackend::default())]
+    backend: FlagBackend,
+    /// The trained model dir, typically output
+    #[arg(long, default_value_t = String::from("./output"))]
+    model_dir: String,
+    #[arg(long, value_enum, default_value_t = ExportFormat::Onnx)]
+    format: ExportFormat,
+    /// Where the exported model is written, `model.onnx` in the model dir by default
+    #[arg(long)]
+    output: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum ExportFormat {
+    /// An ONNX graph taking normalized `[batch, height, width]` images and returning logits,
+    /// with the preprocessing and class names in its metadata
+    Onnx,
+}
+
+pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
+    let model_dir = PathBuf::from(&args.model_dir);
+    let config = TrainingConfig::load(model_dir.join("model_config.json").to_str().unwrap())?;
+    let classes = class_names(load_classes(&model_dir)?, config.model.num_classes());
+
+    let output = match &args.output {
+        Some(path) => PathBuf::from(path),
+        None => model_dir.join(match args.format {
+            ExportFormat::Onnx => "model.onnx",
+        }),
+    };
+
+    dispatch(
+        &args.backend,
+        Export {
+            model_dir,
+            config,
+            classes,
+            output,
+        },
+    )
+}
+
+struct Export {
+    model_dir: PathBuf,
+    config: TrainingConfig,
+    classes: Vec<String>,
+    output: PathBuf,
+}
+
+impl BackendTask for Export {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        export::<B::InnerBackend>(self, device)
+    }
+}
+
+fn export<B: Backend>(task: Export, device: B::Device) -> crate::Result<()> {
+    let model = task
+        .config
+        .load_model::<B>(task.model_dir.join("model"), &device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load the trained model: {error}"))?;
+
+    let preprocessor = task.config.preprocessor();
+    let mut metadata = preprocessor.metadata();
+    metadata.push(("classes", serde_json::to_string(&task.classes)?));
+    if let serde_json::Value::String(architecture) = serde_json::to_value(task.config.architecture)?
+    {
+        metadata.push(("architecture", architecture));
+    }
+
+    let onnx = model.to_onnx(preprocessor.size(), task.classes.len(), &metadata);
+    std::fs::write(&task.output, onnx)?;
+    println!("Exported the model to {}", task.output.display());
+
+    Ok(())
+}
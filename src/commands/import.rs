@@ -0,0 +1,358 @@
+use super::*;
+use crate::api::neural_network::{
+    Architecture, StoredTensor, StoredValues, encode_safetensors, read_initializers,
+    read_input_major_weights,
+};
+use burn::tensor::backend::AutodiffBackend;
+use burn_store::{
+    ApplyError, ApplyResult, KeyRemapper, PyTorchToBurnAdapter, PytorchStore, SafetensorsStore,
+};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(clap::Args)]
+pub(crate) struct Arguments {
+    #[arg(long, default_value_t = FlagBackend::default())]
+    backend: FlagBackend,
+    /// A PyTorch state dict (`.pt`, `.pth`), a `.safetensors` file or an `.onnx` model
+    weights: String,
+    /// The training config describing the model, `train`'s defaults when unset
+    #[arg(long)]
+    config: Option<String>,
+    /// Where the imported model is saved, the config's `output_dir` by default
+    #[arg(long)]
+    output_dir: Option<String>,
+    /// Renames the source tensors before they're matched, as `<regex>=<replacement>`.
+    /// Repeatable, applied in order after `--remap-file`
+    #[arg(long, value_name = "PATTERN=REPLACEMENT")]
+    remap: Vec<String>,
+    /// TOML file with `[[remap]]` entries, each a `pattern` and its `replacement`
+    #[arg(long)]
+    remap_file: Option<String>,
+    /// The key PyTorch checkpoints nest the state dict under, e.g. `state_dict`
+    #[arg(long)]
+    key: Option<String>,
+    /// Save even when some of the model's tensors weren't found, they keep their initial values
+    #[arg(long)]
+    allow_partial: bool,
+}
+
+#[derive(Deserialize)]
+struct RemapFile {
+    #[serde(default)]
+    remap: Vec<Remap>,
+}
+
+#[derive(Deserialize)]
+struct Remap {
+    pattern: String,
+    replacement: String,
+}
+
+pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
+    let mut config = if let Some(path) = &args.config {
+        TrainingConfig::try_from_path(path.into())?
+    } else {
+        TrainingConfig::builder().build()
+    };
+
+    if let Some(output_dir) = &args.output_dir {
+        config.output_dir = output_dir.clone();
+    }
+
+    let mut remaps = match &args.remap_file {
+        Some(path) => read_remap_file(path)?,
+        None => Vec::new(),
+    };
+    for remap in &args.remap {
+        let (pattern, replacement) = remap.split_once('=').ok_or_else(|| {
+            color_eyre::eyre::eyre!("--remap {remap} is not in the form PATTERN=REPLACEMENT")
+        })?;
+        remaps.push((pattern.to_owned(), replacement.to_owned()));
+    }
+    // Last, so custom names can be mapped onto the original `conv1`, `linear1`, ... as well
+    if config.architecture == Architecture::Cnn {
+        remaps.extend(config.model.legacy_key_remapping());
+    }
+    let remapper = KeyRemapper::from_patterns(remaps)
+        .map_err(|error| color_eyre::eyre::eyre!("Invalid remap pattern: {error}"))?;
+
+    let store = Store::open(Path::new(&args.weights), args.key.as_deref(), remapper)?;
+
+    dispatch(
+        &args.backend,
+        Import {
+            config,
+            store,
+            allow_partial: args.allow_partial,
+        },
+    )
+}
+
+fn read_remap_file(path: &str) -> crate::Result<Vec<(String, String)>> {
+    let contents = std::fs::read_to_string(path)?;
+    let file = toml::from_str::<RemapFile>(&contents)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to parse {path}: {error}"))?;
+
+    Ok(file
+        .remap
+        .into_iter()
+        .map(|remap| (remap.pattern, remap.replacement))
+        .collect())
+}
+
+/// Where the weights are read from. Every format is expected in PyTorch's layout, e.g. linear
+/// weights as `[outputs, inputs]`
+enum Store {
+    Pytorch(PytorchStore),
+    /// Safetensors files, and ONNX models repacked as safetensors in memory. ONNX matrices the
+    /// graph multiplies as `[inputs, outputs]` are transposed first
+    Safetensors(SafetensorsStore),
+}
+
+impl Store {
+    fn open(path: &Path, key: Option<&str>, remapper: KeyRemapper) -> crate::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        if key.is_some() && !matches!(extension, "pt" | "pth") {
+            return Err(color_eyre::eyre::eyre!(
+                "--key only applies to PyTorch checkpoints"
+            ));
+        }
+
+        // Mismatches are collected and reported per tensor instead of failing on the first one
+        match extension {
+            "pt" | "pth" => {
+                let mut store = PytorchStore::from_file(path)
+                    .remap(remapper)
+                    .validate(false)
+                    .allow_partial(true);
+                if let Some(key) = key {
+                    store = store.with_top_level_key(key);
+                }
+                Ok(Store::Pytorch(store))
+            }
+            "safetensors" => Ok(Store::safetensors(
+                SafetensorsStore::from_file(path),
+                remapper,
+            )),
+            "onnx" => {
+                let bytes = std::fs::read(path)?;
+                let (tensors, input_major) = read_initializers(&bytes)
+                    .and_then(|tensors| Ok((tensors, read_input_major_weights(&bytes)?)))
+                    .map_err(|error| {
+                        color_eyre::eyre::eyre!("Failed to read {}: {error}", path.display())
+                    })?;
+                let tensors = tensors
+                    .into_iter()
+                    .map(|tensor| {
+                        // Into PyTorch's `[outputs, inputs]`, like the other formats
+                        let tensor = if input_major.contains(&tensor.name) {
+                            tensor.transposed()
+                        } else {
+                            tensor
+                        };
+                        let stored = StoredTensor {
+                            shape: tensor.shape,
+                            values: StoredValues::F32(tensor.values),
+                        };
+                        (tensor.name, stored)
+                    })
+                    .collect();
+                let bytes = encode_safetensors(&tensors)?;
+                Ok(Store::safetensors(
+                    SafetensorsStore::from_bytes(Some(bytes)),
+                    remapper,
+                ))
+            }
+            _ => Err(color_eyre::eyre::eyre!(
+                "{} is not a .pt, .pth, .safetensors or .onnx file",
+                path.display()
+            )),
+        }
+    }
+
+    fn safetensors(store: SafetensorsStore, remapper: KeyRemapper) -> Self {
+        Store::Safetensors(
+            store
+                .with_from_adapter(PyTorchToBurnAdapter)
+                .remap(remapper)
+                .validate(false)
+                .allow_partial(true),
+        )
+    }
+}
+
+struct Import {
+    config: TrainingConfig,
+    store: Store,
+    allow_partial: bool,
+}
+
+impl BackendTask for Import {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        import::<B::InnerBackend>(self, device)
+    }
+}
+
+fn import<B: burn::prelude::Backend>(mut task: Import, device: B::Device) -> crate::Result<()> {
+    let mut model = task.config.init_model::<B>(&device);
+
+    let result = match &mut task.store {
+        Store::Pytorch(store) => model.load_from(store).map_err(|error| error.to_string()),
+        Store::Safetensors(store) => model.load_from(store).map_err(|error| error.to_string()),
+    }
+    .map_err(|error| color_eyre::eyre::eyre!("Failed to read the weights: {error}"))?;
+
+    print_report(&result);
+
+    let missing = if task.allow_partial {
+        0
+    } else {
+        result.missing.len()
+    };
+    if !result.errors.is_empty() || missing > 0 {
+        return Err(color_eyre::eyre::eyre!(
+            "{} of the model's tensors could not be imported, nothing was saved",
+            result.errors.len() + missing
+        ));
+    }
+
+    let config = task.config;
+    std::fs::create_dir_all(&config.output_dir)?;
+    config.save(&format!("{}/model_config.json", config.output_dir))?;
+    model
+        .save_weights(format!("{}/model", config.output_dir), config.record_format)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to save the model: {error}"))?;
+
+    println!(
+        "\nImported {} tensors into {}",
+        result.applied.len(),
+        config.output_dir
+    );
+
+    Ok(())
+}
+
+/// One line per tensor: the model's tensors with whether they were loaded, then the source
+/// tensors nothing in the model matched
+fn print_report(result: &ApplyResult) {
+    let mut rows = result
+        .applied
+        .iter()
+        .map(|path| ("loaded", path.clone(), String::new()))
+        .collect::<Vec<_>>();
+
+    for error in &result.errors {
+        rows.push(match error {
+            ApplyError::ShapeMismatch {
+                path,
+                expected,
+                found,
+            } => (
+                "mismatch",
+                path.clone(),
+                format!("expected {expected:?}, found {found:?}"),
+            ),
+            ApplyError::DTypeMismatch {
+                path,
+                expected,
+                found,
+            } => (
+                "mismatch",
+                path.clone(),
+                format!("expected {expected:?}, found {found:?}"),
+            ),
+            ApplyError::AdapterError { path, message }
+            | ApplyError::LoadError { path, message } => ("error", path.clone(), message.clone()),
+        });
+    }
+
+    rows.extend(
+        result
+            .missing
+            .iter()
+            .map(|(path, _)| ("missing", path.clone(), "not in the file".to_owned())),
+    );
+    rows.extend(
+        result
+            .unused
+            .iter()
+            .map(|path| ("unused", path.clone(), "not in the model".to_owned())),
+    );
+
+    let path_width = rows
+        .iter()
+        .map(|(_, path, _)| path.len())
+        .max()
+        .unwrap_or_default();
+    for (status, path, detail) in rows {
+        let row = format!("{status:<8}  {path:<path_width$}  {detail}");
+        println!("{}", row.trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::neural_network::Classifier;
+    use burn::{backend::NdArray, prelude::*, tensor::Distribution};
+
+    type B = NdArray;
+
+    /// Maps `export`'s numbered weight names onto the model's paths, in the order the layers run
+    fn remapper(onnx: &[u8]) -> KeyRemapper {
+        let mut counts = std::collections::BTreeMap::<String, usize>::new();
+        let patterns = read_initializers(onnx)
+            .unwrap()
+            .into_iter()
+            .filter_map(|tensor| {
+                let (kind, _) = tensor.name.rsplit_once('_')?;
+                let path = match kind {
+                    "conv.weight" | "conv.bias" => "conv_blocks.{}.",
+                    "linear.weight" | "linear.bias" => "linears.{}.",
+                    _ => return None,
+                };
+                let kind = kind.trim_start_matches("linear.");
+                let count = counts.entry(kind.to_owned()).or_default();
+                let path = path.replace("{}", &count.to_string()) + kind;
+                *count += 1;
+                Some((format!("^{}$", tensor.name.replace('.', r"\.")), path))
+            })
+            .collect::<Vec<_>>();
+        KeyRemapper::from_patterns(patterns).unwrap()
+    }
+
+    #[test]
+    fn exported_onnx_models_import_unchanged() {
+        let device = Default::default();
+        let config = TrainingConfig::builder().build();
+        let model = config.init_model::<B>(&device);
+        let onnx = model.to_onnx([28, 28], 10, &[]);
+
+        let path = std::env::temp_dir().join(format!("import-test-{}.onnx", std::process::id()));
+        std::fs::write(&path, &onnx).unwrap();
+        let store = Store::open(&path, None, remapper(&onnx));
+        std::fs::remove_file(&path).unwrap();
+        let Store::Safetensors(mut store) = store.unwrap() else {
+            unreachable!()
+        };
+
+        let mut imported = config.init_model::<B>(&device);
+        let result = imported.load_from(&mut store).unwrap();
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        assert!(result.missing.is_empty(), "{:?}", result.missing);
+
+        let images = Tensor::<B, 3>::random([4, 28, 28], Distribution::Default, &device);
+        let difference = (model.forward(images.clone()) - imported.forward(images))
+            .abs()
+            .max()
+            .into_scalar();
+        assert!(difference < 1e-5, "the logits differ by {difference}");
+    }
+}
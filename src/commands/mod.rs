@@ -1,67 +1,667 @@
 use bon::Builder;
-use burn::optim::AdamConfig;
 use serde::{Deserialize, Serialize};
 
-use crate::api::neural_network::ModelConfig;
+use crate::api::neural_network::{
+    Architecture, AugmentationConfig, EarlyStoppingConfig, ImageFolderConfig, LegacyModelConfig,
+    MlpConfig, ModelConfig, Network, OptimizerConfig, Preprocessor, QuantizationConfig,
+    RecordFormat, ResNetConfig, SchedulerConfig,
+};
 
+mod backend;
+pub(crate) mod convert;
+mod data;
+pub(crate) mod evaluate;
 pub(crate) mod example;
+pub(crate) mod export;
+pub(crate) mod import;
 pub(crate) mod predict;
+pub(crate) mod prune;
+pub(crate) mod quantize;
 #[cfg(debug_assertions)]
 pub(crate) mod scaffold;
 pub(crate) mod train;
 
-#[derive(clap::ValueEnum, Clone, Default)]
-pub(crate) enum FlagBackend {
-    #[default]
-    Ndarray,
-    Cuda,
+pub(crate) use backend::{BackendTask, FlagBackend, dispatch};
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum FlagRecordFormat {
+    /// Half precision named MessagePack
+    Compact,
+    /// Full precision named MessagePack
+    NamedMpk,
+    /// Full precision bincode
+    Binary,
+    /// Full precision indented JSON, for diffing weights
+    PrettyJson,
 }
 
-impl std::fmt::Display for FlagBackend {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            FlagBackend::Ndarray => f.write_str("ndarray"),
-            FlagBackend::Cuda => f.write_str("cuda"),
+impl From<FlagRecordFormat> for RecordFormat {
+    fn from(flag: FlagRecordFormat) -> Self {
+        match flag {
+            FlagRecordFormat::Compact => RecordFormat::Compact,
+            FlagRecordFormat::NamedMpk => RecordFormat::NamedMpk,
+            FlagRecordFormat::Binary => RecordFormat::Binary,
+            FlagRecordFormat::PrettyJson => RecordFormat::PrettyJson,
         }
     }
 }
 
+/// Bumped whenever a saved `model_config.json` can no longer be read as-is
+const CONFIG_FORMAT_VERSION: u32 = 4;
+
+fn current_format_version() -> u32 {
+    CONFIG_FORMAT_VERSION
+}
+
+fn default_validation_split() -> f64 {
+    0.1
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Builder)]
 pub(crate) struct TrainingConfig {
-    #[builder(default = ModelConfig::new(10, 512))]
+    #[builder(default = CONFIG_FORMAT_VERSION)]
+    #[serde(default = "current_format_version")]
+    format_version: u32,
+    /// The model family that's trained
+    #[builder(default)]
+    #[serde(default)]
+    architecture: Architecture,
+    /// The CNN, its `num_classes` is used by every architecture
+    #[builder(default = ModelConfig::new(10, vec![512]))]
     model: ModelConfig,
-    #[builder(default = AdamConfig::new())]
-    optimizer: AdamConfig,
+    /// Only read with `architecture = "resnet"`, defaults when unset
+    resnet: Option<ResNetConfig>,
+    /// Only read with `architecture = "mlp"`, defaults when unset
+    mlp: Option<MlpConfig>,
+    /// Adam unless configured otherwise
+    #[builder(default)]
+    #[serde(deserialize_with = "OptimizerConfig::deserialize_or_adam")]
+    optimizer: OptimizerConfig,
     #[builder(default = 10)]
     num_epochs: usize,
     #[builder(default = 64)]
     batch_size: usize,
     #[builder(default = 4)]
     num_workers: usize,
+    /// Stops once validation stops improving and keeps the best epoch's model, off when unset
+    early_stopping: Option<EarlyStoppingConfig>,
     #[builder(default = 42)]
     seed: u64,
+    /// Fraction of the training data held out for validation, the test set is only used to
+    /// report on the final model
+    #[builder(default = 0.1)]
+    #[serde(default = "default_validation_split")]
+    validation_split: f64,
+    /// Where the scheduler starts, or peaks for warmup and one-cycle
     #[builder(default = 1.0e-4)]
     learning_rate: f64,
+    /// How the learning rate changes over the run, constant when unset
+    #[builder(default)]
+    #[serde(default)]
+    scheduler: SchedulerConfig,
     #[builder(default = "./output".into())]
     output_dir: String,
+    /// How the weights and checkpoints are saved, compact half precision by default
+    #[builder(default)]
+    #[serde(default)]
+    record_format: RecordFormat,
+    /// Set by `quantize`, the CNN is then read from int8 weights instead
+    quantization: Option<QuantizationConfig>,
+    /// Directory holding the raw MNIST IDX files. When unset, MNIST is downloaded instead.
+    data_dir: Option<String>,
+    /// Random changes to the training images, seeded from `seed`. Off when unset
+    augmentation: Option<AugmentationConfig>,
+    /// Train on a `root/<class_name>/*.png` image tree instead of MNIST
+    image_folder: Option<ImageFolderConfig>,
 }
 
 impl TrainingConfig {
     fn try_from_path(path: std::path::PathBuf) -> crate::Result<Self> {
         let contents = std::fs::read_to_string(&path)?;
-        toml::from_str(&contents)
-            .map_err(|e| color_eyre::eyre::eyre!("Failed to parse config: {e}"))
+        let error = |e: toml::de::Error| color_eyre::eyre::eyre!("Failed to parse config: {e}");
+
+        let mut table = toml::from_str::<toml::Table>(&contents).map_err(error)?;
+        if !Self::migrate_hidden_size(&mut table) {
+            // Parsed from the text when nothing changed, so errors point at the line
+            return toml::from_str(&contents).map_err(error);
+        }
+        table.try_into().map_err(error)
+    }
+
+    /// Configs from before `hidden_sizes` gave the single dense layer as `hidden_size`, which
+    /// becomes a one-element `hidden_sizes`. Returns whether anything changed
+    fn migrate_hidden_size(config: &mut toml::Table) -> bool {
+        let Some(model) = config
+            .get_mut("model")
+            .and_then(|model| model.as_table_mut())
+        else {
+            return false;
+        };
+        if model.contains_key("hidden_sizes") {
+            return false;
+        }
+        let Some(hidden_size) = model.remove("hidden_size") else {
+            return false;
+        };
+
+        model.insert("hidden_sizes".to_owned(), vec![hidden_size].into());
+        true
     }
 
     fn save(&self, path: &str) -> crate::Result<()> {
-        std::fs::write(path, serde_json::to_string_pretty(&self.model)?)?;
+        std::fs::write(path, serde_json::to_string_pretty(&self.saved_value()?)?)?;
         Ok(())
     }
 
+    /// What `save` writes, so saved configs can be compared with this one
+    fn saved_value(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(self)
+    }
+
+    /// Errors when the config saved at `path` differs from this one, listing the changed keys.
+    ///
+    /// Loading brings older formats up to date first. What only that filled in, the format
+    /// version and the layer order the saved model was trained with, is taken over instead of
+    /// being reported as a change
+    fn ensure_matches_saved(&mut self, path: &str) -> crate::Result<()> {
+        let mut saved = Self::load(path)?;
+        // Formats before 3 had no `architecture_version`, loading sets the order they used
+        if saved.format_version < 3 {
+            self.model = self
+                .model
+                .clone()
+                .with_architecture_version(saved.model.architecture_version());
+        }
+        saved.format_version = self.format_version;
+
+        let saved = saved.saved_value()?;
+        let current = self.saved_value()?;
+
+        if saved == current {
+            return Ok(());
+        }
+
+        let changed = match (saved.as_object(), current.as_object()) {
+            (Some(saved), Some(current)) => saved
+                .keys()
+                .chain(current.keys().filter(|key| !saved.contains_key(*key)))
+                .filter(|key| saved.get(*key) != current.get(*key))
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => "the whole config".to_owned(),
+        };
+
+        Err(color_eyre::eyre::eyre!(
+            "The config saved in {path} does not match the current one (changed: {changed})"
+        ))
+    }
+
+    fn init_model<B: burn::prelude::Backend>(&self, device: &B::Device) -> Network<B> {
+        match self.architecture {
+            Architecture::Cnn => Network::Cnn(self.model.init(device)),
+            Architecture::ResNet => {
+                Network::ResNet(self.resnet().init(self.model.num_classes(), device))
+            }
+            Architecture::Mlp => Network::Mlp(self.mlp().init(
+                self.model.num_classes(),
+                self.input_size(),
+                device,
+            )),
+        }
+    }
+
+    /// Loads the weights `Network::save_weights` wrote for this config's architecture
+    fn load_model<B: burn::prelude::Backend>(
+        &self,
+        path: std::path::PathBuf,
+        device: &B::Device,
+    ) -> Result<Network<B>, burn::record::RecorderError> {
+        if let Some(quantization) = &self.quantization {
+            if self.architecture != Architecture::Cnn {
+                return Err(burn::record::RecorderError::Unknown(
+                    "Only CNN models are quantized".to_owned(),
+                ));
+            }
+            return self
+                .model
+                .init_with_int8_file(path, quantization, device)
+                .map(Network::Cnn);
+        }
+
+        match self.architecture {
+            Architecture::Cnn => self
+                .model
+                .init_with_file(path, self.record_format, device)
+                .map(Network::Cnn),
+            Architecture::ResNet => self
+                .resnet()
+                .init_with_file(self.model.num_classes(), path, self.record_format, device)
+                .map(Network::ResNet),
+            Architecture::Mlp => self
+                .mlp()
+                .init_with_file(
+                    self.model.num_classes(),
+                    self.input_size(),
+                    path,
+                    self.record_format,
+                    device,
+                )
+                .map(Network::Mlp),
+        }
+    }
+
+    fn resnet(&self) -> ResNetConfig {
+        self.resnet.clone().unwrap_or_else(ResNetConfig::new)
+    }
+
+    fn mlp(&self) -> MlpConfig {
+        self.mlp.clone().unwrap_or_else(MlpConfig::new)
+    }
+
+    /// Pixels in one preprocessed image
+    fn input_size(&self) -> usize {
+        let [height, width] = self.preprocessor().size();
+        height * width
+    }
+
+    /// How images are turned into model inputs, matching the data the model is trained on
+    fn preprocessor(&self) -> Preprocessor {
+        match &self.image_folder {
+            Some(folder) => folder.preprocessor(),
+            None => Preprocessor::new(28, 28),
+        }
+    }
+
     fn load(path: &str) -> crate::Result<Self> {
         let contents = std::fs::read_to_string(path)?;
-        serde_json::from_str::<Self>(&contents).map_err(|error| {
-            color_eyre::eyre::eyre!("Failed to load config: {}", error.to_string())
-        })
+        let mut value = serde_json::from_str::<serde_json::Value>(&contents)
+            .map_err(|error| color_eyre::eyre::eyre!("Failed to load config: {error}"))?;
+
+        // Output dirs from before the format version only saved the model section
+        if value.get("format_version").is_none() {
+            let model = serde_json::from_value::<LegacyModelConfig>(value)
+                .map_err(|error| color_eyre::eyre::eyre!("Failed to load config: {error}"))?;
+            return Ok(Self::builder()
+                .model(model.into())
+                .format_version(0)
+                .build());
+        }
+
+        let version = value
+            .get("format_version")
+            .and_then(|version| version.as_u64());
+
+        // Format 1 described the model with a single `hidden_size` and fixed conv layers
+        if version == Some(1)
+            && let Some(model) = value.get_mut("model")
+        {
+            let legacy = serde_json::from_value::<LegacyModelConfig>(model.take())
+                .map_err(|error| color_eyre::eyre::eyre!("Failed to load config: {error}"))?;
+            *model = serde_json::to_value(ModelConfig::from(legacy))?;
+        }
+
+        // Format 2 models came before batch norm and the corrected layer order
+        if version == Some(2)
+            && let Some(model) = value
+                .get_mut("model")
+                .and_then(|model| model.as_object_mut())
+        {
+            model.insert("batch_norm".to_owned(), false.into());
+            model.insert("architecture_version".to_owned(), 1.into());
+        }
+
+        let config = serde_json::from_value::<Self>(value)
+            .map_err(|error| color_eyre::eyre::eyre!("Failed to load config: {error}"))?;
+
+        if config.format_version > CONFIG_FORMAT_VERSION {
+            return Err(color_eyre::eyre::eyre!(
+                "{path} uses config format {}, this build only reads up to {CONFIG_FORMAT_VERSION}",
+                config.format_version
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+const CLASSES_FILE: &str = "classes.json";
+
+/// Saves the class names next to the model, a class' index is its position in the list
+fn save_classes(model_dir: &str, classes: &[String]) -> crate::Result<()> {
+    let path = std::path::Path::new(model_dir).join(CLASSES_FILE);
+    std::fs::write(path, serde_json::to_string_pretty(classes)?)?;
+    Ok(())
+}
+
+/// Returns `None` for models without named classes, e.g. MNIST digits
+fn load_classes(model_dir: &std::path::Path) -> crate::Result<Option<Vec<String>>> {
+    let path = model_dir.join(CLASSES_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = std::fs::read_to_string(path)?;
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load classes: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::neural_network::{Classifier, normalize};
+    use burn::{
+        backend::NdArray,
+        nn::{
+            Dropout, DropoutConfig, Linear, LinearConfig, Relu,
+            conv::{Conv2d, Conv2dConfig},
+            pool::{AdaptiveAvgPool2d, AdaptiveAvgPool2dConfig},
+        },
+        prelude::*,
+    };
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    type B = NdArray;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("config-test-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(dir: &Path, architecture: Architecture) -> TrainingConfig {
+        TrainingConfig::builder()
+            .architecture(architecture)
+            .model(ModelConfig::new(10, vec![32]))
+            .num_epochs(3)
+            .seed(7)
+            .learning_rate(1.0e-3)
+            .record_format(RecordFormat::NamedMpk)
+            .data_dir("./mnist".to_owned())
+            .output_dir(dir.to_string_lossy().into_owned())
+            .build()
+    }
+
+    /// Saves `config`, rewrites the saved JSON with `downgrade` and loads it back
+    fn reload(
+        config: &TrainingConfig,
+        dir: &Path,
+        downgrade: impl FnOnce(&mut serde_json::Value),
+    ) -> TrainingConfig {
+        let path = dir.join("model_config.json");
+        let path = path.to_str().unwrap();
+        config.save(path).unwrap();
+
+        let mut value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        downgrade(&mut value);
+        std::fs::write(path, value.to_string()).unwrap();
+
+        TrainingConfig::load(path).unwrap()
+    }
+
+    fn images() -> Tensor<B, 3> {
+        let pixels = (0..2 * 28 * 28)
+            .map(|index| (index * 37 % 256) as f32)
+            .collect();
+        normalize(pixels, [2, 28, 28], &Default::default())
+    }
+
+    fn assert_same_logits(expected: Tensor<B, 2>, actual: Tensor<B, 2>) {
+        assert_eq!(expected.dims(), actual.dims());
+        assert_eq!(
+            expected.into_data().to_vec::<f32>().unwrap(),
+            actual.into_data().to_vec::<f32>().unwrap()
+        );
+    }
+
+    /// The model `predict` would run for the config saved in `dir`
+    fn predict_model(dir: &Path) -> Network<B> {
+        let config = TrainingConfig::load(dir.join("model_config.json").to_str().unwrap()).unwrap();
+        config
+            .load_model(dir.join("model"), &Default::default())
+            .unwrap()
+    }
+
+    /// The network models were saved as before the layers were configurable
+    #[derive(Debug, Module)]
+    struct LegacyModel<B: Backend> {
+        conv1: Conv2d<B>,
+        conv2: Conv2d<B>,
+        pool: AdaptiveAvgPool2d,
+        dropout: Dropout,
+        linear1: Linear<B>,
+        linear2: Linear<B>,
+        activation: Relu,
+    }
+
+    impl LegacyModel<B> {
+        /// Saves a new legacy model in `dir`, returning it as read back from the file
+        fn saved(dir: &Path, hidden_size: usize, format: RecordFormat) -> Self {
+            let device = Default::default();
+            let model = Self {
+                conv1: Conv2dConfig::new([1, 8], [3, 3]).init(&device),
+                conv2: Conv2dConfig::new([8, 16], [3, 3]).init(&device),
+                pool: AdaptiveAvgPool2dConfig::new([8, 8]).init(),
+                dropout: DropoutConfig::new(0.5).init(),
+                linear1: LinearConfig::new(16 * 8 * 8, hidden_size).init(&device),
+                linear2: LinearConfig::new(hidden_size, 10).init(&device),
+                activation: Relu::new(),
+            };
+
+            let path = dir.join("model");
+            format.save(model.clone(), &path).unwrap();
+            model.load_record(format.load(path, &device).unwrap())
+        }
+
+        fn forward(&self, images: Tensor<B, 3>) -> Tensor<B, 2> {
+            let [batch_size, height, width] = images.dims();
+            let x = images.reshape([batch_size, 1, height, width]);
+            let x = self.dropout.forward(self.conv1.forward(x));
+            let x = self.dropout.forward(self.conv2.forward(x));
+            let x = self
+                .pool
+                .forward(self.activation.forward(x))
+                .flatten::<2>(1, 3);
+            let x = self.dropout.forward(self.linear1.forward(x));
+            self.linear2.forward(self.activation.forward(x))
+        }
+    }
+
+    #[test]
+    fn saved_configs_load_unchanged() {
+        for architecture in [Architecture::Cnn, Architecture::Mlp, Architecture::ResNet] {
+            let dir = temp_dir(&format!("{architecture:?}"));
+            let config = config(&dir, architecture);
+            let loaded = reload(&config, &dir, |_| ());
+            assert_eq!(loaded.saved_value().unwrap(), config.saved_value().unwrap());
+
+            let model = config.init_model::<B>(&Default::default());
+            let expected = model.forward(images());
+            model
+                .save_weights(dir.join("model"), config.record_format)
+                .unwrap();
+            assert_same_logits(expected, predict_model(&dir).forward(images()));
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn format_3_configs_default_to_adam() {
+        let dir = temp_dir("format-3");
+        let config = config(&dir, Architecture::Cnn);
+        let mut loaded = reload(&config, &dir, |value| {
+            value["format_version"] = json!(3);
+            value["optimizer"].as_object_mut().unwrap().remove("kind");
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(loaded.optimizer, OptimizerConfig::Adam(_)));
+        assert_eq!(loaded.format_version, 3);
+        loaded.format_version = CONFIG_FORMAT_VERSION;
+        assert_eq!(loaded.saved_value().unwrap(), config.saved_value().unwrap());
+    }
+
+    #[test]
+    fn format_2_models_keep_their_layer_order() {
+        let dir = temp_dir("format-2");
+        let config = config(&dir, Architecture::Cnn);
+        let mut loaded = reload(&config, &dir, |value| {
+            value["format_version"] = json!(2);
+            value["optimizer"].as_object_mut().unwrap().remove("kind");
+            let model = value["model"].as_object_mut().unwrap();
+            model.remove("batch_norm");
+            model.remove("architecture_version");
+        });
+
+        // Trained with the old layer order, so that's what's saved and read back
+        let model = Network::Cnn(
+            config
+                .model
+                .clone()
+                .with_architecture_version(1)
+                .init::<B>(&Default::default()),
+        );
+        let expected = model.forward(images());
+        model
+            .save_weights(dir.join("model"), config.record_format)
+            .unwrap();
+        let predicted = predict_model(&dir).forward(images());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        loaded.format_version = CONFIG_FORMAT_VERSION;
+        let expected_config = TrainingConfig {
+            model: config.model.clone().with_architecture_version(1),
+            ..config
+        };
+        assert_eq!(
+            loaded.saved_value().unwrap(),
+            expected_config.saved_value().unwrap()
+        );
+        assert_same_logits(expected, predicted);
+    }
+
+    #[test]
+    fn resuming_older_formats_only_reports_real_changes() {
+        let dir = temp_dir("resume");
+        let path = dir.join("model_config.json");
+        let path = path.to_str().unwrap();
+        let config = config(&dir, Architecture::Cnn);
+
+        let v3 = |value: &mut serde_json::Value| {
+            value["format_version"] = json!(3);
+            value["optimizer"].as_object_mut().unwrap().remove("kind");
+        };
+        reload(&config, &dir, v3);
+        let mut resumed = config.clone();
+        let v3_result = resumed.ensure_matches_saved(path);
+        let v3_model = serde_json::to_value(&resumed.model).unwrap();
+
+        reload(&config, &dir, |value| {
+            v3(value);
+            value["format_version"] = json!(2);
+            let model = value["model"].as_object_mut().unwrap();
+            model.remove("batch_norm");
+            model.remove("architecture_version");
+        });
+        let mut resumed = config.clone();
+        let v2_result = resumed.ensure_matches_saved(path);
+        let v2_model = serde_json::to_value(&resumed.model).unwrap();
+
+        let mut changed = config.clone();
+        changed.num_epochs += 1;
+        let changed = changed.ensure_matches_saved(path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        v3_result.unwrap();
+        assert_eq!(v3_model, serde_json::to_value(&config.model).unwrap());
+        v2_result.unwrap();
+        // Carries on with the layer order the checkpoints were trained with
+        assert_eq!(v2_model["architecture_version"], 1);
+        assert!(
+            changed
+                .unwrap_err()
+                .to_string()
+                .ends_with("(changed: num_epochs)")
+        );
+    }
+
+    #[test]
+    fn format_1_configs_load_legacy_models() {
+        let dir = temp_dir("format-1");
+        let config = config(&dir, Architecture::Cnn);
+        let loaded = reload(&config, &dir, |value| {
+            value["format_version"] = json!(1);
+            value["optimizer"].as_object_mut().unwrap().remove("kind");
+            value["model"] = json!({ "num_classes": 10, "hidden_size": 32, "dropout": 0.5 });
+        });
+        let legacy = LegacyModel::saved(&dir, 32, config.record_format);
+        let predicted = predict_model(&dir).forward(images());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected_model = ModelConfig::new(10, vec![32]).with_architecture_version(0);
+        assert_eq!(
+            serde_json::to_value(&loaded.model).unwrap(),
+            serde_json::to_value(&expected_model).unwrap()
+        );
+        assert_eq!(loaded.num_epochs, 3);
+        assert_eq!(loaded.data_dir.as_deref(), Some("./mnist"));
+        assert_same_logits(legacy.forward(images()), predicted);
+    }
+
+    #[test]
+    fn model_only_configs_load_legacy_models() {
+        let dir = temp_dir("legacy");
+        std::fs::write(
+            dir.join("model_config.json"),
+            r#"{"num_classes": 10, "hidden_size": 64, "dropout": 0.5}"#,
+        )
+        .unwrap();
+        let loaded = TrainingConfig::load(dir.join("model_config.json").to_str().unwrap()).unwrap();
+        let legacy = LegacyModel::saved(&dir, 64, loaded.record_format);
+        let predicted = predict_model(&dir).forward(images());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.format_version, 0);
+        assert_eq!(loaded.architecture, Architecture::Cnn);
+        assert_eq!(loaded.record_format, RecordFormat::Compact);
+        assert_same_logits(legacy.forward(images()), predicted);
+    }
+
+    #[test]
+    fn toml_configs_with_hidden_size_still_parse() {
+        let dir = temp_dir("hidden-size");
+        let mut table = toml::Table::try_from(config(&dir, Architecture::Cnn)).unwrap();
+        let model = table["model"].as_table_mut().unwrap();
+        model.remove("hidden_sizes");
+        model.insert("hidden_size".to_owned(), 128.into());
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, table.to_string()).unwrap();
+        let config = TrainingConfig::try_from_path(path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        let model = serde_json::to_value(&config.model).unwrap();
+        assert_eq!(model["hidden_sizes"], serde_json::json!([128]));
+        assert_eq!(model["architecture_version"], 2);
+    }
+
+    #[test]
+    fn newer_formats_are_rejected() {
+        let dir = temp_dir("newer");
+        let config = config(&dir, Architecture::Cnn);
+        let path = dir.join("model_config.json");
+        config.save(path.to_str().unwrap()).unwrap();
+        let mut value: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        value["format_version"] = json!(CONFIG_FORMAT_VERSION + 1);
+        std::fs::write(&path, value.to_string()).unwrap();
+
+        let error = TrainingConfig::load(path.to_str().unwrap()).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(error.to_string().contains("this build only reads up to"));
     }
 }
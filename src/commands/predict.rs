@@ -1,10 +1,11 @@
 use super::*;
+use crate::api::neural_network::{Classifier, PreprocessMode, Preprocessor, normalize};
 use burn::{
     prelude::*,
-    record::{CompactRecorder, Recorder},
+    tensor::{activation::softmax, backend::AutodiffBackend},
 };
-use image::ImageReader;
-use std::str::FromStr;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 
 #[derive(clap::Args)]
 pub(crate) struct Arguments {
@@ -13,72 +14,476 @@ pub(crate) struct Arguments {
     /// The trained model dir, typically output
     #[arg(long, default_value_t = String::from("./output"))]
     model_dir: String,
-    /// Path to the image to infer from
-    image: String,
+    /// How many images are run through the model at once
+    #[arg(long, default_value_t = 64)]
+    batch_size: usize,
+    /// How the predictions are printed
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+    /// Shorthand for `--output json`
+    #[arg(long, conflicts_with = "output")]
+    json: bool,
+    /// Also list the k most likely classes with their probabilities
+    #[arg(long, default_value_t = 1)]
+    top_k: usize,
+    /// Report "uncertain" when the most likely class is below this probability
+    #[arg(long, value_parser = probability)]
+    min_confidence: Option<f32>,
+    /// Invert the colors, for dark digits on a light background
+    #[arg(long)]
+    invert: bool,
+    /// How images are fit to the model's input size
+    #[arg(long, value_enum, default_value_t = FlagPreprocess::Resize)]
+    preprocess: FlagPreprocess,
+    /// How the weights were saved, the format in the model's config when unset
+    #[arg(long, value_enum)]
+    format: Option<FlagRecordFormat>,
+    /// Images to infer from, as files, directories or glob patterns
+    #[arg(required = true)]
+    images: Vec<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum OutputFormat {
+    /// Aligned columns for reading in a terminal
+    Table,
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum FlagPreprocess {
+    /// Resize the whole image
+    Resize,
+    /// Crop, fit into 20x20 and center by mass like MNIST, inverting light backgrounds.
+    /// Best for photos and scans of handwritten digits
+    Mnist,
+}
+
+impl From<FlagPreprocess> for PreprocessMode {
+    fn from(flag: FlagPreprocess) -> Self {
+        match flag {
+            FlagPreprocess::Resize => PreprocessMode::Resize,
+            FlagPreprocess::Mnist => PreprocessMode::Mnist,
+        }
+    }
+}
+
+/// Parses a probability, which has to be in `0..=1`
+fn probability(value: &str) -> Result<f32, String> {
+    let probability = value.parse::<f32>().map_err(|error| error.to_string())?;
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(format!("{probability} is not between 0 and 1"));
+    }
+    Ok(probability)
 }
 
 pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
-    let path = std::path::PathBuf::from_str(&args.model_dir)?;
-    let config = TrainingConfig::load(path.join("model_config.json").to_str().unwrap())?;
-
-    let image_path = std::path::PathBuf::from_str(&args.image)?;
-
-    let img = ImageReader::open(path.clone())
-        .map_err(|error| {
-            color_eyre::eyre::eyre!(format!(
-                "Failed to load image from {}: {}",
-                image_path.to_str().unwrap(),
-                error.to_string()
-            ))
-        })?
-        .decode()
-        .map_err(|_| color_eyre::eyre::eyre!("Failed to decode image"))?
-        .to_luma8();
-
-    let resized = image::imageops::resize(&img, 28, 28, image::imageops::FilterType::Lanczos3);
-
-    // Normalize pixels
-    let resized = resized
-        .into_raw()
-        .into_iter()
-        .map(|pixel| pixel as f32 / 255.0)
-        .collect();
-
-    let result = match &args.backend {
-        FlagBackend::Ndarray => predict::<burn::backend::NdArray>(
-            path,
-            config,
-            burn::backend::ndarray::NdArrayDevice::default(),
-            resized,
-        ),
-        FlagBackend::Cuda => todo!(),
-    }?;
+    let model_dir = PathBuf::from(&args.model_dir);
+    let mut config = TrainingConfig::load(model_dir.join("model_config.json").to_str().unwrap())?;
+    if let Some(format) = args.format {
+        config.record_format = format.into();
+    }
+    let classes = load_classes(&model_dir)?;
 
-    println!("{result}");
+    let images = collect_images(&args.images)?;
+    if images.is_empty() {
+        return Err(color_eyre::eyre::eyre!(
+            "No images found in {}",
+            args.images.join(", ")
+        ));
+    }
 
-    Ok(())
+    let mut preprocessor = config.preprocessor().with_mode(args.preprocess.into());
+    if args.invert {
+        preprocessor = preprocessor.with_invert(true);
+    }
+
+    dispatch(
+        &args.backend,
+        Predict {
+            model_dir,
+            config,
+            preprocessor,
+            classes,
+            images,
+            batch_size: args.batch_size.max(1),
+            output: if args.json {
+                OutputFormat::Json
+            } else {
+                args.output
+            },
+            top_k: args.top_k.max(1),
+            min_confidence: args.min_confidence.unwrap_or_default(),
+        },
+    )
 }
 
-fn predict<B>(
-    model_dir: std::path::PathBuf,
+struct Predict {
+    model_dir: PathBuf,
     config: TrainingConfig,
-    device: B::Device,
-    image_data: Vec<f32>,
-) -> crate::Result<u8>
+    preprocessor: Preprocessor,
+    classes: Option<Vec<String>>,
+    images: Vec<PathBuf>,
+    batch_size: usize,
+    output: OutputFormat,
+    top_k: usize,
+    min_confidence: f32,
+}
+
+impl BackendTask for Predict {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        predict::<B::InnerBackend>(self, device)
+    }
+}
+
+impl Predict {
+    fn class_name(&self, index: usize) -> String {
+        match self.classes.as_ref().and_then(|classes| classes.get(index)) {
+            Some(class) => class.clone(),
+            None => index.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ClassProbability {
+    class: String,
+    index: usize,
+    probability: f32,
+}
+
+struct Prediction<'a> {
+    path: &'a Path,
+    /// Most likely classes first, always holds at least one
+    top_k: Vec<ClassProbability>,
+    /// The most likely class is below `--min-confidence`
+    uncertain: bool,
+}
+
+impl Prediction<'_> {
+    fn best(&self) -> &ClassProbability {
+        &self.top_k[0]
+    }
+
+    fn class(&self) -> &str {
+        if self.uncertain {
+            "uncertain"
+        } else {
+            &self.best().class
+        }
+    }
+
+    /// Top-k as `class=probability` pairs, for the table and CSV output
+    fn top_k_summary(&self) -> String {
+        self.top_k
+            .iter()
+            .map(|class| format!("{}={:.4}", class.class, class.probability))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn predict<B>(task: Predict, device: B::Device) -> crate::Result<()>
 where
     B: Backend,
 {
-    let record = CompactRecorder::new()
-        .load(model_dir, &device)
-        .expect("Trained model doesn't exist");
+    // The model is loaded once and reused for every batch
+    let model = task
+        .config
+        .load_model::<B>(task.model_dir.join("model"), &device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load the trained model: {error}"))?;
+    let [height, width] = task.preprocessor.size();
+
+    let path_width = task
+        .images
+        .iter()
+        .map(|path| path.display().to_string().len())
+        .max()
+        .unwrap_or_default();
+    print_header(task.output, path_width, task.top_k > 1);
+
+    for paths in task.images.chunks(task.batch_size) {
+        // An image that fails to load gets an error row, the rest of the batch still runs
+        let opened = paths
+            .iter()
+            .map(|path| task.preprocessor.open(path))
+            .collect::<Vec<_>>();
+        let loaded = opened.iter().filter(|pixels| pixels.is_ok()).count();
+        let pixels = opened
+            .iter()
+            .filter_map(|pixels| pixels.as_ref().ok())
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+
+        let top_k = task.top_k.min(task.config.model.num_classes());
+        let (probabilities, indices) = if loaded == 0 {
+            (Vec::new(), Vec::new())
+        } else {
+            let images = normalize::<B>(pixels, [loaded, height, width], &device);
+            let (probabilities, indices) =
+                softmax(model.forward(images), 1).topk_with_indices(top_k, 1);
+            (
+                probabilities.into_data().iter::<f32>().collect::<Vec<_>>(),
+                indices.into_data().iter::<i64>().collect::<Vec<_>>(),
+            )
+        };
+        let mut predictions = probabilities.chunks(top_k).zip(indices.chunks(top_k));
+
+        for (path, opened) in paths.iter().zip(&opened) {
+            if let Err(error) = opened {
+                println!(
+                    "{}",
+                    error_row(task.output, path_width, task.top_k > 1, path, error)?
+                );
+                continue;
+            }
+
+            let (probabilities, indices) = predictions
+                .next()
+                .expect("Every loaded image should have a prediction");
+            let top_k = probabilities
+                .iter()
+                .zip(indices)
+                .map(|(&probability, &index)| ClassProbability {
+                    class: task.class_name(index as usize),
+                    index: index as usize,
+                    probability,
+                })
+                .collect::<Vec<_>>();
+            let uncertain = top_k[0].probability < task.min_confidence;
+
+            println!(
+                "{}",
+                prediction_row(
+                    task.output,
+                    path_width,
+                    task.top_k > 1,
+                    &Prediction {
+                        path,
+                        top_k,
+                        uncertain,
+                    },
+                )?
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands every input into image files: directories are listed (sorted, not recursive) and
+/// anything that doesn't exist as-is is treated as a glob pattern.
+fn collect_images(inputs: &[String]) -> crate::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+
+    for input in inputs {
+        let path = Path::new(input);
+
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.retain(|path| is_image(path));
+            entries.sort();
+            images.extend(entries);
+        } else if path.exists() {
+            images.push(path.to_owned());
+        } else if input.contains(['*', '?', '[']) {
+            for entry in glob::glob(input)? {
+                let path = entry?;
+                if is_image(&path) {
+                    images.push(path);
+                }
+            }
+        } else {
+            return Err(color_eyre::eyre::eyre!("{input} does not exist"));
+        }
+    }
+
+    Ok(images)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.is_file() && image::ImageFormat::from_path(path).is_ok()
+}
+
+fn print_header(output: OutputFormat, path_width: usize, with_top_k: bool) {
+    let top_k = if with_top_k { "top_k" } else { "" };
+
+    match output {
+        OutputFormat::Table => {
+            let header = format!(
+                "{:<path_width$}  {:>10}  {:>10}  {top_k}",
+                "path", "class", "confidence"
+            );
+            println!("{}", header.trim_end());
+        }
+        OutputFormat::Csv if with_top_k => println!("path,class,confidence,top_k,error"),
+        OutputFormat::Csv => println!("path,class,confidence,error"),
+        OutputFormat::Json => {}
+    }
+}
+
+fn prediction_row(
+    output: OutputFormat,
+    path_width: usize,
+    with_top_k: bool,
+    prediction: &Prediction,
+) -> crate::Result<String> {
+    let path = prediction.path.display().to_string();
+    let confidence = prediction.best().probability;
+    let top_k = if with_top_k {
+        prediction.top_k_summary()
+    } else {
+        String::new()
+    };
+
+    let row = match output {
+        OutputFormat::Table => format!(
+            "{path:<path_width$}  {:>10}  {confidence:>10.4}  {top_k}",
+            prediction.class()
+        )
+        .trim_end()
+        .to_owned(),
+        OutputFormat::Csv if with_top_k => format!(
+            "{},{},{confidence:.4},{},",
+            csv_field(&path),
+            csv_field(prediction.class()),
+            csv_field(&top_k)
+        ),
+        OutputFormat::Csv => format!(
+            "{},{},{confidence:.4},",
+            csv_field(&path),
+            csv_field(prediction.class())
+        ),
+        OutputFormat::Json => serde_json::to_string(&serde_json::json!({
+            "path": path,
+            "class": prediction.class(),
+            "index": (!prediction.uncertain).then_some(prediction.best().index),
+            "confidence": confidence,
+            "uncertain": prediction.uncertain,
+            "top_k": prediction.top_k,
+        }))?,
+    };
+
+    Ok(row)
+}
+
+/// The row of an image that couldn't be loaded, in place of its prediction
+fn error_row(
+    output: OutputFormat,
+    path_width: usize,
+    with_top_k: bool,
+    path: &Path,
+    error: &impl std::fmt::Display,
+) -> crate::Result<String> {
+    let path = path.display().to_string();
+    let error = error.to_string();
+
+    let row = match output {
+        OutputFormat::Table => {
+            format!("{path:<path_width$}  {:>10}  {:>10}  {error}", "error", "-")
+        }
+        OutputFormat::Csv if with_top_k => {
+            format!("{},error,,,{}", csv_field(&path), csv_field(&error))
+        }
+        OutputFormat::Csv => format!("{},error,,{}", csv_field(&path), csv_field(&error)),
+        OutputFormat::Json => serde_json::to_string(&serde_json::json!({
+            "path": path,
+            "error": error,
+        }))?,
+    };
+
+    Ok(row)
+}
+
+/// Quotes a CSV field when it contains a separator, quote or newline
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Command {
+        #[command(flatten)]
+        args: Arguments,
+    }
+
+    fn min_confidence(value: &str) -> Result<Option<f32>, clap::Error> {
+        Command::try_parse_from(["predict", "--min-confidence", value, "digit.png"])
+            .map(|command| command.args.min_confidence)
+    }
+
+    fn prediction(path: &Path) -> Prediction<'_> {
+        Prediction {
+            path,
+            top_k: vec![
+                ClassProbability {
+                    class: "7".to_owned(),
+                    index: 7,
+                    probability: 0.75,
+                },
+                ClassProbability {
+                    class: "1".to_owned(),
+                    index: 1,
+                    probability: 0.25,
+                },
+            ],
+            uncertain: false,
+        }
+    }
+
+    #[test]
+    fn min_confidence_is_a_probability() {
+        assert_eq!(min_confidence("0").unwrap(), Some(0.0));
+        assert_eq!(min_confidence("0.75").unwrap(), Some(0.75));
+        assert_eq!(min_confidence("1").unwrap(), Some(1.0));
+
+        for value in ["1.5", "-0.1", "NaN", "high"] {
+            assert!(min_confidence(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn error_rows_line_up_with_predictions() {
+        let path = Path::new("digits/bad, file.png");
+        let error = "unexpected end of file";
 
-    let model = config.model.init::<B>(&device).load_record(record);
+        let row = error_row(OutputFormat::Table, 24, false, path, &error).unwrap();
+        assert_eq!(
+            row,
+            "digits/bad, file.png           error           -  unexpected end of file"
+        );
 
-    // TODO(_): Probably move to api instead?
-    let images =
-        Tensor::<B, 4>::from_floats(image_data.as_slice(), &device).reshape([1usize, 28, 28]);
-    let output = model.forward(images);
-    let predicted = output.argmax(1).flatten::<1>(0, 1).into_scalar();
+        // Every CSV row has as many fields as the header
+        for with_top_k in [false, true] {
+            let columns = if with_top_k { 5 } else { 4 };
+            let prediction = prediction_row(OutputFormat::Csv, 0, with_top_k, &prediction(path));
+            let error = error_row(OutputFormat::Csv, 0, with_top_k, path, &error).unwrap();
+            // The path is quoted, so it adds one comma that isn't a separator
+            assert_eq!(prediction.unwrap().matches(',').count(), columns);
+            assert_eq!(error.matches(',').count(), columns);
+            assert!(error.starts_with("\"digits/bad, file.png\",error,"));
+            assert!(error.ends_with(",unexpected end of file"));
+        }
 
-    Ok(predicted.elem::<u8>())
+        let row = error_row(OutputFormat::Json, 0, true, path, &error).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&row).unwrap(),
+            serde_json::json!({ "path": "digits/bad, file.png", "error": "unexpected end of file" })
+        );
+    }
 }
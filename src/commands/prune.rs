@@ -0,0 +1,348 @@
+use super::{
+    evaluate::{Evaluation, class_names},
+    train::{Fit, build_dataloaders},
+    *,
+};
+use crate::api::neural_network::{LayerSparsity, Model, Network, PruneMask};
+use burn::{
+    prelude::*,
+    record::{BinBytesRecorder, FullPrecisionSettings, Recorder},
+    tensor::backend::AutodiffBackend,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub(crate) struct Arguments {
+    #[arg(long, default_value_t = FlagBackend::default())]
+    backend: FlagBackend,
+    /// The trained model dir, typically output
+    #[arg(long, default_value_t = String::from("./output"))]
+    model_dir: String,
+    /// Where the pruned model is saved, `pruned` in the model dir by default
+    #[arg(long)]
+    output_dir: Option<String>,
+    /// Fraction of every conv kernel and linear layer set to zero, e.g. 0.8
+    #[arg(long)]
+    sparsity: f64,
+    #[arg(long, value_enum, default_value_t = Schedule::OneShot)]
+    schedule: Schedule,
+    /// Fine-tuning epochs, one per stage when pruning gradually. 0 only prunes
+    #[arg(long, default_value_t = 1)]
+    epochs: usize,
+    /// Fine-tune with this learning rate instead of the configured one
+    #[arg(long)]
+    learning_rate: Option<f64>,
+    /// Read MNIST from the IDX files in this directory instead of downloading it
+    #[arg(long)]
+    data_dir: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum Schedule {
+    /// Prune to the target sparsity once, then fine-tune for all the epochs
+    OneShot,
+    /// Prune a little more before every fine-tuning epoch, reaching the target on the last one
+    Gradual,
+}
+
+impl Schedule {
+    /// The sparsity of every stage and how many epochs it's fine-tuned for
+    fn stages(self, sparsity: f64, epochs: usize) -> Vec<(f64, usize)> {
+        match self {
+            Schedule::OneShot => vec![(sparsity, epochs)],
+            // Cubic, so most weights go early while there are epochs left to recover
+            Schedule::Gradual => (1..=epochs)
+                .map(|epoch| {
+                    let progress = epoch as f64 / epochs as f64;
+                    (sparsity * (1.0 - (1.0 - progress).powi(3)), 1)
+                })
+                .collect(),
+        }
+    }
+}
+
+pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
+    if !(0.0..1.0).contains(&args.sparsity) {
+        return Err(color_eyre::eyre::eyre!(
+            "--sparsity must be at least 0 and below 1, got {}",
+            args.sparsity
+        ));
+    }
+    if matches!(args.schedule, Schedule::Gradual) && args.epochs == 0 {
+        return Err(color_eyre::eyre::eyre!(
+            "Gradual pruning needs at least one fine-tuning epoch"
+        ));
+    }
+
+    let model_dir = PathBuf::from(&args.model_dir);
+    let mut config = TrainingConfig::load(model_dir.join("model_config.json").to_str().unwrap())?;
+
+    if config.architecture != Architecture::Cnn {
+        return Err(color_eyre::eyre::eyre!("Only CNN models can be pruned"));
+    }
+    if config.quantization.is_some() {
+        return Err(color_eyre::eyre::eyre!(
+            "{} holds int8 weights, prune the float model instead",
+            model_dir.display()
+        ));
+    }
+
+    if let Some(data_dir) = &args.data_dir {
+        config.data_dir = Some(data_dir.clone());
+    }
+    let output_dir = args
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| model_dir.join("pruned").to_string_lossy().into_owned());
+
+    dispatch(
+        &args.backend,
+        Prune {
+            classes: class_names(load_classes(&model_dir)?, config.model.num_classes()),
+            model_dir,
+            output_dir,
+            config,
+            stages: args.schedule.stages(args.sparsity, args.epochs),
+            learning_rate: args.learning_rate,
+        },
+    )
+}
+
+struct Prune {
+    model_dir: PathBuf,
+    output_dir: String,
+    config: TrainingConfig,
+    classes: Vec<String>,
+    stages: Vec<(f64, usize)>,
+    learning_rate: Option<f64>,
+}
+
+impl BackendTask for Prune {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        prune::<B>(self, device)
+    }
+}
+
+#[derive(Serialize)]
+struct PruneReport {
+    /// Validation accuracy of the model before it was pruned
+    accuracy: f64,
+    stages: Vec<Stage>,
+}
+
+#[derive(Serialize)]
+struct Stage {
+    sparsity: f64,
+    epochs: usize,
+    /// Validation accuracy right after pruning
+    pruned_accuracy: f64,
+    /// Validation accuracy after fine-tuning, unset without fine-tuning epochs
+    fine_tuned_accuracy: Option<f64>,
+    layers: Vec<LayerSparsity>,
+}
+
+fn prune<B: AutodiffBackend>(task: Prune, device: B::Device) -> crate::Result<()> {
+    let mut config = task.config;
+    B::seed(&device, config.seed);
+
+    let trained = config
+        .load_model::<B::InnerBackend>(task.model_dir.join("model"), &device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load the trained model: {error}"))?;
+    let mut model = cnn(trained)?;
+    config.model = config.model.resaved();
+    config.output_dir = task.output_dir;
+    std::fs::create_dir_all(&config.output_dir)?;
+
+    // Every stage runs its epochs in full, in the output dir
+    let mut fine_tune = config.clone();
+    fine_tune.early_stopping = None;
+    if let Some(learning_rate) = task.learning_rate {
+        fine_tune.learning_rate = learning_rate;
+    }
+
+    let dataloaders = build_dataloaders::<B>(&fine_tune, 0)?;
+    let accuracy = |model: &Model<B::InnerBackend>| {
+        Evaluation::run(
+            &Network::Cnn(model.clone()),
+            dataloaders.valid.clone(),
+            task.classes.clone(),
+        )
+        .map(|evaluation| evaluation.accuracy)
+    };
+
+    let mut report = PruneReport {
+        accuracy: accuracy(&model)?,
+        stages: Vec::new(),
+    };
+    println!("Validation accuracy before pruning: {:.4}", report.accuracy);
+
+    let count = task.stages.len();
+    for (index, (sparsity, epochs)) in task.stages.into_iter().enumerate() {
+        let (pruned, mask) = model.prune(sparsity);
+        model = pruned;
+        let pruned_accuracy = accuracy(&model)?;
+
+        let mut fine_tuned_accuracy = None;
+        if epochs > 0 {
+            fine_tune.num_epochs = epochs;
+            // One scheduler step per training batch
+            let total_steps = dataloaders.train.num_items().div_ceil(fine_tune.batch_size) * epochs;
+
+            let tuned = Fit {
+                model: to_autodiff::<B>(&fine_tune, model, &device)?,
+                scheduler: fine_tune
+                    .scheduler
+                    .init(fine_tune.learning_rate, total_steps),
+                config: &fine_tune,
+                device: device.clone(),
+                checkpoint: None,
+                train: dataloaders.train.clone(),
+                valid: dataloaders.valid.clone(),
+                mask: Some(PruneMask::from_inner(mask)),
+            }
+            .run()?;
+
+            model = cnn(tuned)?;
+            fine_tuned_accuracy = Some(accuracy(&model)?);
+        }
+
+        let stage = Stage {
+            sparsity,
+            epochs,
+            pruned_accuracy,
+            fine_tuned_accuracy,
+            layers: model.sparsity(),
+        };
+        print_stage(index + 1, count, &stage);
+        report.stages.push(stage);
+    }
+
+    // The test set is only seen here, after pruning is done
+    let model = Network::Cnn(model);
+    let test = Evaluation::run(&model, dataloaders.test, task.classes)?;
+    println!(
+        "\nTest set: accuracy {:.4}, loss {:.4} over {} images",
+        test.accuracy, test.loss, test.items
+    );
+
+    config.save(&format!("{}/model_config.json", config.output_dir))?;
+    if let Some(classes) = load_classes(&task.model_dir)? {
+        save_classes(&config.output_dir, &classes)?;
+    }
+    std::fs::write(
+        format!("{}/test_report.json", config.output_dir),
+        serde_json::to_string_pretty(&test)?,
+    )?;
+    std::fs::write(
+        format!("{}/prune_report.json", config.output_dir),
+        serde_json::to_string_pretty(&report)?,
+    )?;
+    model
+        .save_weights(format!("{}/model", config.output_dir), config.record_format)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to save the model: {error}"))?;
+
+    println!("Saved the pruned model to {}", config.output_dir);
+
+    Ok(())
+}
+
+fn cnn<B: Backend>(network: Network<B>) -> crate::Result<Model<B>> {
+    match network {
+        Network::Cnn(model) => Ok(model),
+        _ => Err(color_eyre::eyre::eyre!("Only CNN models can be pruned")),
+    }
+}
+
+/// The learner hands back the model without autodiff, so it's moved back through its record
+/// before the next stage trains it
+fn to_autodiff<B: AutodiffBackend>(
+    config: &TrainingConfig,
+    model: Model<B::InnerBackend>,
+    device: &B::Device,
+) -> crate::Result<Network<B>> {
+    let recorder = BinBytesRecorder::<FullPrecisionSettings>::default();
+    let bytes = recorder
+        .record(Network::Cnn(model).into_record(), ())
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to copy the model: {error}"))?;
+    let record = recorder
+        .load(bytes, device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to copy the model: {error}"))?;
+
+    Ok(config.init_model::<B>(device).load_record(record))
+}
+
+fn print_stage(number: usize, count: usize, stage: &Stage) {
+    let fine_tuned = stage
+        .fine_tuned_accuracy
+        .map(|accuracy| format!(", {accuracy:.4} after fine-tuning"))
+        .unwrap_or_default();
+    println!(
+        "\nStage {number}/{count}: sparsity {:.4}, validation accuracy {:.4} after pruning{fine_tuned}",
+        stage.sparsity, stage.pruned_accuracy
+    );
+
+    let layer_width = stage
+        .layers
+        .iter()
+        .map(|layer| layer.layer.len())
+        .max()
+        .unwrap_or_default()
+        .max("layer".len());
+    let shapes = stage
+        .layers
+        .iter()
+        .map(|layer| format!("{:?}", layer.shape))
+        .collect::<Vec<_>>();
+    let shape_width = shapes
+        .iter()
+        .map(String::len)
+        .max()
+        .unwrap_or_default()
+        .max("shape".len());
+
+    println!(
+        "{:<layer_width$}  {:<shape_width$}  {:>9}  {:>8}",
+        "layer", "shape", "zeros", "sparsity"
+    );
+    for (layer, shape) in stage.layers.iter().zip(&shapes) {
+        println!(
+            "{:<layer_width$}  {shape:<shape_width$}  {:>9}  {:>8.4}",
+            layer.layer, layer.zeros, layer.sparsity
+        );
+    }
+
+    let zeros = stage.layers.iter().map(|layer| layer.zeros).sum::<usize>();
+    let weights = stage
+        .layers
+        .iter()
+        .map(|layer| layer.shape.iter().product::<usize>())
+        .sum::<usize>();
+    println!(
+        "{:<layer_width$}  {:<shape_width$}  {zeros:>9}  {:>8.4}",
+        "total",
+        "",
+        zeros as f64 / weights.max(1) as f64
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_reach_the_target() {
+        assert_eq!(Schedule::OneShot.stages(0.8, 3), [(0.8, 3)]);
+        assert_eq!(Schedule::OneShot.stages(0.8, 0), [(0.8, 0)]);
+
+        for epochs in [1, 2, 5] {
+            let stages = Schedule::Gradual.stages(0.8, epochs);
+            assert_eq!(stages.len(), epochs);
+            assert!(stages.iter().all(|(_, epochs)| *epochs == 1));
+            assert!(stages.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            assert_eq!(stages.last().unwrap().0, 0.8);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use super::{
+    data::{Datasets, ItemDataset, dataloader},
+    evaluate::{Evaluation, class_names},
+    *,
+};
+use crate::api::neural_network::{Granularity, Int8Weights, MnistBatcher, Network};
+use burn::{
+    data::dataset::{Dataset, transform::PartialDataset},
+    prelude::*,
+    tensor::backend::AutodiffBackend,
+};
+use std::{path::PathBuf, sync::Arc};
+
+#[derive(clap::Args)]
+pub(crate) struct Arguments {
+    #[arg(long, default_value_t = FlagBackend::default())]
+    backend: FlagBackend,
+    /// The trained model dir, typically output
+    #[arg(long, default_value_t = String::from("./output"))]
+    model_dir: String,
+    /// Where the quantized model is saved, `int8` in the model dir by default
+    #[arg(long)]
+    output_dir: Option<String>,
+    /// How finely the weights are scaled, activations always get one range per layer
+    #[arg(long, value_enum, default_value_t = FlagGranularity::PerTensor)]
+    granularity: FlagGranularity,
+    /// Training images the activation ranges are calibrated on
+    #[arg(long, default_value_t = 1000)]
+    calibration_size: usize,
+    /// Read MNIST from the IDX files in this directory instead of downloading it
+    #[arg(long)]
+    data_dir: Option<String>,
+    /// How many images are run through the model at once
+    #[arg(long, default_value_t = 64)]
+    batch_size: usize,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum FlagGranularity {
+    /// One scale for each weight tensor
+    PerTensor,
+    /// One scale for each output channel, closer to the float model
+    PerChannel,
+}
+
+impl From<FlagGranularity> for Granularity {
+    fn from(flag: FlagGranularity) -> Self {
+        match flag {
+            FlagGranularity::PerTensor => Granularity::PerTensor,
+            FlagGranularity::PerChannel => Granularity::PerChannel,
+        }
+    }
+}
+
+pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
+    let model_dir = PathBuf::from(&args.model_dir);
+    let mut config = TrainingConfig::load(model_dir.join("model_config.json").to_str().unwrap())?;
+
+    if config.architecture != Architecture::Cnn {
+        return Err(color_eyre::eyre::eyre!("Only CNN models can be quantized"));
+    }
+    if config.quantization.is_some() {
+        return Err(color_eyre::eyre::eyre!(
+            "{} is already quantized",
+            model_dir.display()
+        ));
+    }
+
+    if let Some(data_dir) = &args.data_dir {
+        config.data_dir = Some(data_dir.clone());
+    }
+    config.batch_size = args.batch_size.max(1);
+
+    let datasets = Datasets::load(&config)?;
+    let classes = class_names(load_classes(&model_dir)?, config.model.num_classes());
+    // The training data is shuffled with the seed, so its start is a random sample
+    let calibration_size = args.calibration_size.clamp(1, datasets.train.len());
+    let calibration = Arc::new(PartialDataset::new(
+        datasets.train.clone(),
+        0,
+        calibration_size,
+    ));
+
+    let output_dir = args
+        .output_dir
+        .as_ref()
+        .map_or_else(|| model_dir.join("int8"), PathBuf::from);
+
+    dispatch(
+        &args.backend,
+        Quantize {
+            model_dir,
+            output_dir,
+            config,
+            classes,
+            granularity: args.granularity.into(),
+            calibration,
+            test: datasets.test,
+        },
+    )
+}
+
+struct Quantize {
+    model_dir: PathBuf,
+    output_dir: PathBuf,
+    config: TrainingConfig,
+    classes: Vec<String>,
+    granularity: Granularity,
+    calibration: ItemDataset,
+    test: ItemDataset,
+}
+
+impl BackendTask for Quantize {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        quantize::<B::InnerBackend>(self, device)
+    }
+}
+
+fn quantize<B: Backend>(task: Quantize, device: B::Device) -> crate::Result<()> {
+    let float = task
+        .config
+        .load_model::<B>(task.model_dir.join("model"), &device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load the trained model: {error}"))?;
+    let Network::Cnn(model) = &float else {
+        return Err(color_eyre::eyre::eyre!("Only CNN models can be quantized"));
+    };
+
+    let mut activation_ranges = Vec::new();
+    let calibration = dataloader::<B>(&task.config, MnistBatcher::default(), task.calibration);
+    for batch in calibration.iter() {
+        model.calibrate(batch.images, &mut activation_ranges);
+    }
+
+    let mut config = task.config.clone();
+    config.model = config.model.resaved();
+    config.quantization = Some(QuantizationConfig {
+        granularity: task.granularity,
+        activation_ranges,
+    });
+
+    let weights_path = task
+        .output_dir
+        .join("model")
+        .with_extension(Int8Weights::EXTENSION);
+    std::fs::create_dir_all(&task.output_dir)?;
+    model.to_int8(task.granularity).save(&weights_path)?;
+    config.save(task.output_dir.join("model_config.json").to_str().unwrap())?;
+    if let Some(classes) = load_classes(&task.model_dir)? {
+        save_classes(task.output_dir.to_str().unwrap(), &classes)?;
+    }
+
+    // Read back the way `predict` does, so the report is on what was saved
+    let quantized = config
+        .load_model::<B>(task.output_dir.join("model"), &device)
+        .map_err(|error| color_eyre::eyre::eyre!("Failed to load the quantized model: {error}"))?;
+
+    let test = |config: &TrainingConfig| {
+        dataloader::<B>(config, MnistBatcher::default(), task.test.clone())
+    };
+    let float = Evaluation::run(&float, test(&task.config), task.classes.clone())?;
+    let int8 = Evaluation::run(&quantized, test(&config), task.classes)?;
+
+    println!("Test set over {} images", int8.items);
+    println!("{:<6}  {:>8}  {:>8}", "", "accuracy", "loss");
+    println!(
+        "{:<6}  {:>8.4}  {:>8.4}",
+        "float", float.accuracy, float.loss
+    );
+    println!("{:<6}  {:>8.4}  {:>8.4}", "int8", int8.accuracy, int8.loss);
+    println!(
+        "{:<6}  {:>+8.4}  {:>+8.4}",
+        "delta",
+        int8.accuracy - float.accuracy,
+        int8.loss - float.loss
+    );
+    std::fs::write(
+        task.output_dir.join("test_report.json"),
+        serde_json::to_string_pretty(&int8)?,
+    )?;
+
+    let float_path = task
+        .model_dir
+        .join("model")
+        .with_extension(task.config.record_format.extension());
+    println!(
+        "\nSaved the int8 model to {} ({} KB, the float weights are {} KB)",
+        task.output_dir.display(),
+        std::fs::metadata(&weights_path)?.len() / 1024,
+        std::fs::metadata(float_path)?.len() / 1024
+    );
+
+    Ok(())
+}
@@ -1,90 +1,356 @@
-use super::*;
-use crate::api::neural_network::MnistBatcher;
+use super::{
+    data::{Datasets, ItemDataset, Loader, dataloader},
+    evaluate::{Evaluation, class_names},
+    *,
+};
+use crate::api::neural_network::{
+    AugmentedDataset, Classifier, EarlyStopping, MaskedOptimizer, MnistBatcher, Network,
+    OptimizerConfig, PruneMask, RecordFormat, Scheduler,
+};
 use burn::{
-    backend::Autodiff,
-    data::{dataloader::DataLoaderBuilder, dataset::vision::MnistDataset},
+    optim::Optimizer,
     prelude::*,
-    record::CompactRecorder,
+    record::{
+        BinFileRecorder, CompactRecorder, FullPrecisionSettings, NamedMpkFileRecorder,
+        PrettyJsonFileRecorder,
+    },
     tensor::backend::AutodiffBackend,
     train::{
         LearnerBuilder, LearningStrategy,
-        metric::{AccuracyMetric, LossMetric},
+        checkpoint::KeepLastNCheckpoints,
+        metric::{AccuracyMetric, LearningRateMetric, LossMetric},
     },
 };
+use std::sync::Arc;
 
 #[derive(clap::Args)]
 pub(crate) struct Arguments {
     #[arg(long, default_value_t = FlagBackend::default())]
     backend: FlagBackend,
+    /// Read MNIST from the IDX files in this directory instead of downloading it
+    #[arg(long)]
+    data_dir: Option<String>,
+    /// Continue from a checkpoint in the output dir, the latest one unless an epoch is given
+    #[arg(long, value_name = "EPOCH", require_equals = true)]
+    resume: Option<Option<usize>>,
     config: Option<String>,
 }
 
 pub(crate) fn run(args: &Arguments) -> crate::Result<()> {
-    let config = if let Some(path) = &args.config {
+    let mut config = if let Some(path) = &args.config {
         TrainingConfig::try_from_path(path.into())?
     } else {
         TrainingConfig::builder().build()
     };
 
-    match args.backend {
-        FlagBackend::Ndarray => train::<Autodiff<burn::backend::NdArray>>(
-            config,
-            burn::backend::ndarray::NdArrayDevice::default(),
-        ),
-        FlagBackend::Cuda => train::<Autodiff<burn::backend::Cuda>>(
+    if let Some(data_dir) = &args.data_dir {
+        config.data_dir = Some(data_dir.clone());
+    }
+
+    dispatch(
+        &args.backend,
+        Train {
             config,
-            burn::backend::cuda::CudaDevice::default(),
-        ),
+            resume: args.resume,
+        },
+    )
+}
+
+struct Train {
+    config: TrainingConfig,
+    resume: Option<Option<usize>>,
+}
+
+impl BackendTask for Train {
+    type Output = ();
+
+    fn run<B: AutodiffBackend>(self, device: B::Device) -> crate::Result<()> {
+        train::<B>(self.config, self.resume, device)
     }
 }
 
-fn train<B>(config: TrainingConfig, device: B::Device) -> crate::Result<()>
+fn train<B>(
+    mut config: TrainingConfig,
+    resume: Option<Option<usize>>,
+    device: B::Device,
+) -> crate::Result<()>
 where
     B: AutodiffBackend,
 {
     std::fs::create_dir_all(&config.output_dir)?;
-    config.save(&format!("{}/model_config.json", config.output_dir))?;
 
     B::seed(&device, config.seed);
 
-    let batcher = MnistBatcher::default();
-
-    let dataloader_train = DataLoaderBuilder::new(batcher.clone())
-        .batch_size(config.batch_size)
-        .shuffle(config.seed)
-        .num_workers(config.num_workers)
-        .build(MnistDataset::train());
-
-    let dataloader_test = DataLoaderBuilder::new(batcher)
-        .batch_size(config.batch_size)
-        .shuffle(config.seed)
-        .num_workers(config.num_workers)
-        .build(MnistDataset::test());
-
-    let learner = LearnerBuilder::new(&config.output_dir)
-        .metric_train_numeric(AccuracyMetric::new())
-        .metric_valid_numeric(AccuracyMetric::new())
-        .metric_train_numeric(LossMetric::new())
-        .metric_valid_numeric(LossMetric::new())
-        .with_file_checkpointer(CompactRecorder::new())
-        .learning_strategy(LearningStrategy::SingleDevice(device.clone()))
-        .num_epochs(config.num_epochs)
-        .summary()
-        .build(
-            config.model.init::<B>(&device),
-            config.optimizer.init(),
-            config.learning_rate,
-        );
-
-    let result = learner.fit(dataloader_train, dataloader_test);
-
-    result
-        .model
-        .save_file(
-            format!("{}/model", config.output_dir),
-            &CompactRecorder::new(),
-        )
+    // Found first, so augmentation carries on from the checkpoint's epoch
+    let checkpoint = resume
+        .map(|epoch| find_checkpoint(&config, epoch))
+        .transpose()?;
+    let dataloaders = build_dataloaders::<B>(&config, checkpoint.unwrap_or_default())?;
+
+    // Image folders decide how many outputs the model needs
+    if let Some(classes) = &dataloaders.classes {
+        config.model = config.model.clone().with_num_classes(classes.len());
+        save_classes(&config.output_dir, classes)?;
+    }
+
+    let config_path = format!("{}/model_config.json", config.output_dir);
+    if checkpoint.is_some() {
+        config.ensure_matches_saved(&config_path)?;
+    }
+
+    config.save(&config_path)?;
+
+    // One scheduler step per training batch
+    let total_steps = dataloaders.train.num_items().div_ceil(config.batch_size) * config.num_epochs;
+
+    let model = Fit {
+        model: config.init_model::<B>(&device),
+        scheduler: config.scheduler.init(config.learning_rate, total_steps),
+        config: &config,
+        device,
+        checkpoint,
+        train: dataloaders.train,
+        valid: dataloaders.valid,
+        mask: None,
+    }
+    .run()?;
+
+    // The test set is only seen here, after the model is picked
+    let classes = class_names(dataloaders.classes, config.model.num_classes());
+    let report = Evaluation::run(&model, dataloaders.test, classes)?;
+    println!(
+        "Test set: accuracy {:.4}, loss {:.4} over {} images",
+        report.accuracy, report.loss, report.items
+    );
+    std::fs::write(
+        format!("{}/test_report.json", config.output_dir),
+        serde_json::to_string_pretty(&report)?,
+    )?;
+
+    model
+        .save_weights(format!("{}/model", config.output_dir), config.record_format)
         .expect("Trained model should be saved successfully");
 
     Ok(())
 }
+
+/// Everything the learner needs besides the optimizer
+pub(super) struct Fit<'a, B: AutodiffBackend> {
+    pub(super) config: &'a TrainingConfig,
+    pub(super) device: B::Device,
+    pub(super) checkpoint: Option<usize>,
+    pub(super) model: Network<B>,
+    pub(super) scheduler: Scheduler,
+    pub(super) train: Loader<B>,
+    pub(super) valid: Loader<B::InnerBackend>,
+    /// Pruned weights that are kept at zero after every optimizer step
+    pub(super) mask: Option<PruneMask<B>>,
+}
+
+impl<B: AutodiffBackend> Fit<'_, B> {
+    /// Trains for the configured epochs, returning the model of the best epoch with early
+    /// stopping and of the last one otherwise
+    pub(super) fn run(self) -> crate::Result<Network<B::InnerBackend>> {
+        // Every optimizer is its own type, so the learner is built for whichever one is chosen
+        match &self.config.optimizer {
+            OptimizerConfig::Sgd(optimizer) => self.masked(optimizer.init::<B, Network<B>>()),
+            OptimizerConfig::Adam(optimizer) => self.masked(optimizer.init::<B, Network<B>>()),
+            OptimizerConfig::AdamW(optimizer) => self.masked(optimizer.init::<B, Network<B>>()),
+            OptimizerConfig::RmsProp(optimizer) => self.masked(optimizer.init::<B, Network<B>>()),
+        }
+    }
+
+    fn masked<O>(mut self, optimizer: O) -> crate::Result<Network<B::InnerBackend>>
+    where
+        O: Optimizer<Network<B>, B> + 'static,
+    {
+        match self.mask.take() {
+            Some(mask) => self.learn(MaskedOptimizer::new(optimizer, mask)),
+            None => self.learn(optimizer),
+        }
+    }
+
+    fn learn<O>(self, optimizer: O) -> crate::Result<Network<B::InnerBackend>>
+    where
+        O: Optimizer<Network<B>, B> + 'static,
+    {
+        let config = self.config;
+        let early_stopping = config.early_stopping.clone().map(EarlyStopping::new);
+
+        let mut builder = LearnerBuilder::new(&config.output_dir)
+            .metric_train_numeric(AccuracyMetric::new())
+            .metric_valid_numeric(AccuracyMetric::new())
+            .metric_train_numeric(LossMetric::new())
+            .metric_valid_numeric(LossMetric::new())
+            .metric_train_numeric(LearningRateMetric::new())
+            .learning_strategy(LearningStrategy::SingleDevice(self.device.clone()))
+            .num_epochs(config.num_epochs)
+            .summary();
+
+        // Checkpoints are written like the final weights
+        builder = match config.record_format {
+            RecordFormat::Compact => builder.with_file_checkpointer(CompactRecorder::new()),
+            RecordFormat::NamedMpk => {
+                builder.with_file_checkpointer(NamedMpkFileRecorder::<FullPrecisionSettings>::new())
+            }
+            RecordFormat::Binary => {
+                builder.with_file_checkpointer(BinFileRecorder::<FullPrecisionSettings>::new())
+            }
+            RecordFormat::PrettyJson => builder
+                .with_file_checkpointer(PrettyJsonFileRecorder::<FullPrecisionSettings>::new()),
+        };
+
+        // Restores the model and optimizer state, training picks up at the following epoch
+        if let Some(epoch) = self.checkpoint {
+            builder = builder.checkpoint(epoch);
+        }
+
+        if let Some(early_stopping) = &early_stopping {
+            // The best epoch is at most `patience` epochs back, so its checkpoint is still there
+            builder = builder
+                .early_stopping(early_stopping.clone())
+                .with_checkpointing_strategy(KeepLastNCheckpoints::new(
+                    early_stopping.patience() + 1,
+                ));
+        }
+
+        let learner = builder.build(self.model, optimizer, self.scheduler);
+
+        let mut model = learner.fit(self.train, self.valid).model;
+
+        if let Some(best) = early_stopping.and_then(|early_stopping| early_stopping.best()) {
+            let record = config
+                .record_format
+                .load(
+                    format!("{}/checkpoint/model-{}", config.output_dir, best.epoch),
+                    &self.device,
+                )
+                .map_err(|error| {
+                    color_eyre::eyre::eyre!("Failed to load the best checkpoint: {error}")
+                })?;
+            model = model.load_record(record);
+
+            println!(
+                "Keeping epoch {} with a validation {:?} of {:.4}",
+                best.epoch, best.metric, best.value
+            );
+            std::fs::write(
+                format!("{}/summary.json", config.output_dir),
+                serde_json::to_string_pretty(&serde_json::json!({ "best_epoch": best }))?,
+            )?;
+        }
+
+        Ok(model)
+    }
+}
+
+/// Picks the requested checkpoint epoch, or the latest one saved under `output_dir`
+fn find_checkpoint(config: &TrainingConfig, epoch: Option<usize>) -> crate::Result<usize> {
+    let dir = std::path::Path::new(&config.output_dir).join("checkpoint");
+    let extension = format!(".{}", config.record_format.extension());
+    let mut epochs = std::fs::read_dir(&dir)
+        .map_err(|error| {
+            color_eyre::eyre::eyre!("No checkpoints found in {}: {error}", dir.display())
+        })?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            let epoch = name
+                .to_str()?
+                .strip_prefix("model-")?
+                .strip_suffix(extension.as_str())?;
+            epoch.parse::<usize>().ok()
+        })
+        .collect::<Vec<_>>();
+    epochs.sort();
+
+    let epoch = match epoch {
+        Some(epoch) if epochs.contains(&epoch) => epoch,
+        Some(epoch) => {
+            return Err(color_eyre::eyre::eyre!(
+                "There is no checkpoint for epoch {epoch} in {} (available: {epochs:?})",
+                dir.display()
+            ));
+        }
+        None => *epochs
+            .last()
+            .ok_or_else(|| color_eyre::eyre::eyre!("No checkpoints found in {}", dir.display()))?,
+    };
+
+    if epoch >= config.num_epochs {
+        return Err(color_eyre::eyre::eyre!(
+            "Epoch {epoch} is the last of the {} configured, there is nothing left to train",
+            config.num_epochs
+        ));
+    }
+
+    Ok(epoch)
+}
+
+pub(super) struct Dataloaders<B: AutodiffBackend> {
+    pub(super) train: Loader<B>,
+    /// Held out from the training data, used to pick the model
+    pub(super) valid: Loader<B::InnerBackend>,
+    /// Only used once the model is trained, for the test report
+    pub(super) test: Loader<B::InnerBackend>,
+    /// Class names when the dataset has them
+    pub(super) classes: Option<Vec<String>>,
+}
+
+/// `first_epoch` is the epoch training starts at, only used by augmentation
+pub(super) fn build_dataloaders<B>(
+    config: &TrainingConfig,
+    first_epoch: usize,
+) -> crate::Result<Dataloaders<B>>
+where
+    B: AutodiffBackend,
+{
+    let datasets = Datasets::load(config)?;
+
+    // Augmentation only ever changes the training data
+    let train: ItemDataset = match &config.augmentation {
+        Some(augmentation) => Arc::new(AugmentedDataset::new(
+            datasets.train,
+            augmentation.init(config.seed),
+            first_epoch,
+        )),
+        None => datasets.train,
+    };
+
+    Ok(Dataloaders {
+        train: dataloader(config, MnistBatcher::default(), train),
+        valid: dataloader(config, MnistBatcher::default(), datasets.valid),
+        test: dataloader(config, MnistBatcher::default(), datasets.test),
+        classes: datasets.classes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Command {
+        #[command(flatten)]
+        args: Arguments,
+    }
+
+    fn parse(args: &[&str]) -> Arguments {
+        Command::try_parse_from(["train"].iter().chain(args))
+            .unwrap()
+            .args
+    }
+
+    #[test]
+    fn resume_leaves_the_config_alone() {
+        let args = parse(&["--resume", "config.toml"]);
+        assert_eq!(args.resume, Some(None));
+        assert_eq!(args.config.as_deref(), Some("config.toml"));
+
+        let args = parse(&["--resume=3", "config.toml"]);
+        assert_eq!(args.resume, Some(Some(3)));
+        assert_eq!(args.config.as_deref(), Some("config.toml"));
+
+        assert_eq!(parse(&["config.toml"]).resume, None);
+    }
+}
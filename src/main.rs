@@ -32,6 +32,22 @@ enum Commands {
     Train(train::Arguments),
     /// Infer a number from an image
     Predict(predict::Arguments),
+    /// Score a trained model with per-class metrics and a confusion matrix
+    Evaluate(evaluate::Arguments),
+    /// Write a trained model out for other runtimes, e.g. ONNX
+    Export(export::Arguments),
+    /// Turn PyTorch, safetensors or ONNX weights into a model dir
+    Import(import::Arguments),
+    /// Rewrite a trained model's weights in another record format
+    Convert(convert::Arguments),
+    /// Make an int8 copy of a trained CNN and compare it with the float model
+    ///
+    /// The quantization is simulated: weights are stored as int8, but they're turned back into
+    /// f32 when the model loads and layer inputs are rounded in f32. The file is about 4x smaller
+    /// and the accuracy is what int8 inference would get, memory use and speed stay the same
+    Quantize(quantize::Arguments),
+    /// Zero the smallest weights of a trained CNN, fine-tuning in between
+    Prune(prune::Arguments),
     /// Example command with a subcommand
     Example(example::Arguments),
     #[cfg(debug_assertions)]
@@ -50,6 +66,12 @@ fn main() -> crate::Result<()> {
             Commands::Basic => basic_command(),
             Commands::Train(args) => train::run(args),
             Commands::Predict(args) => predict::run(args),
+            Commands::Evaluate(args) => evaluate::run(args),
+            Commands::Export(args) => export::run(args),
+            Commands::Import(args) => import::run(args),
+            Commands::Convert(args) => convert::run(args),
+            Commands::Quantize(args) => quantize::run(args),
+            Commands::Prune(args) => prune::run(args),
             Commands::Example(args) => example::run(args),
             #[cfg(debug_assertions)]
             Commands::Scaffold(args) => scaffold::run(args),